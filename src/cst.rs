@@ -0,0 +1,155 @@
+use codemap::File;
+use codemap::Spanned;
+use lexer::LexError;
+use lexer::Token;
+use lexer;
+use std::fmt;
+
+// A lossless view of a source file: every token the parser sees, along with
+// the whitespace and comments around it, so the file can be printed back
+// exactly as it was
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trivia {
+  Whitespace(String),
+  // including the `#` or `#[ ]#` around it
+  Comment(String),
+}
+
+impl Trivia {
+  fn text(&self) -> &str {
+    match *self {
+      Trivia::Whitespace(ref x) | Trivia::Comment(ref x) => x,
+    }
+  }
+}
+
+// A token and its trivia. Trailing trivia is whatever follows the token on the
+// same line, like a `# comment` at the end of it; everything else belongs to
+// the next token as leading trivia
+#[derive(Debug, Clone, PartialEq)]
+pub struct CstToken {
+  pub leading: Vec<Trivia>,
+  pub token: Spanned<Token>,
+  // the token's own source text, which is empty for the Exit, End and EOF
+  // tokens the lexer makes up
+  pub text: String,
+  pub trailing: Vec<Trivia>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cst {
+  pub tokens: Vec<CstToken>,
+}
+
+impl fmt::Display for Cst {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for tok in &self.tokens {
+      for trivia in &tok.leading {
+        write!(f, "{}", trivia.text())?;
+      }
+      write!(f, "{}", tok.text)?;
+      for trivia in &tok.trailing {
+        write!(f, "{}", trivia.text())?;
+      }
+    }
+
+    Ok(())
+  }
+}
+
+// Split the source between two tokens into whitespace and comments
+fn gap_trivia(source: &str, start: usize, end: usize, comments: &[(usize, usize)]) -> Vec<Trivia> {
+  let mut trivia = Vec::new();
+  let mut pos = start;
+
+  for &(lo, hi) in comments.iter().filter(|&&(lo, _)| lo >= start && lo < end) {
+    if lo > pos {
+      trivia.push(Trivia::Whitespace(source[pos..lo].to_string()));
+    }
+    trivia.push(Trivia::Comment(source[lo..hi].to_string()));
+    pos = hi;
+  }
+
+  if end > pos {
+    trivia.push(Trivia::Whitespace(source[pos..end].to_string()));
+  }
+
+  trivia
+}
+
+// Divide the trivia between two tokens into what's left on the first token's
+// line and what comes after it
+fn split_trivia(trivia: Vec<Trivia>) -> (Vec<Trivia>, Vec<Trivia>) {
+  let mut trailing = Vec::new();
+  let mut leading = Vec::new();
+
+  for piece in trivia {
+    if !leading.is_empty() {
+      leading.push(piece);
+      continue;
+    }
+
+    match piece {
+      Trivia::Whitespace(ref x) if x.contains('\n') => {
+        let k = x.find('\n').unwrap_or(0);
+        if k > 0 {
+          trailing.push(Trivia::Whitespace(x[..k].to_string()));
+        }
+        leading.push(Trivia::Whitespace(x[k..].to_string()));
+      }
+      Trivia::Comment(ref x) if x.contains('\n') => leading.push(piece.clone()),
+      _ => trailing.push(piece),
+    }
+  }
+
+  (trailing, leading)
+}
+
+pub fn build(input: &File) -> Result<Cst, LexError> {
+  let (tokens, comments) = lexer::lex_lossless(input)?;
+  let source = input.source();
+  let base = input.span.low();
+
+  let offsets = |tok: &Spanned<Token>| {
+    (
+      (tok.span.low() - base) as usize,
+      (tok.span.high() - base) as usize,
+    )
+  };
+  let comments: Vec<(usize, usize)> = comments.iter().map(offsets).collect();
+
+  let mut out: Vec<CstToken> = Vec::new();
+  let mut pos = 0;
+
+  for token in tokens {
+    let (lo, hi) = offsets(&token);
+    let trivia = gap_trivia(source, pos, lo, &comments);
+
+    // a token that ends its line, like End, can't have trailing trivia
+    let leading = match out.last_mut() {
+      Some(prev) if !prev.text.contains('\n') => {
+        let (trailing, leading) = split_trivia(trivia);
+        prev.trailing = trailing;
+        leading
+      }
+      _ => trivia,
+    };
+
+    out.push(CstToken {
+      leading,
+      text: source[lo..hi].to_string(),
+      token,
+      trailing: Vec::new(),
+    });
+    pos = hi;
+  }
+
+  // the lexer always ends with an EOF at the very end of the source, so
+  // there's nothing left over after the last token
+  Ok(Cst { tokens: out })
+}
+
+#[cfg(test)]
+#[path = "./tests/cst.rs"]
+mod tests;
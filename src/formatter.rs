@@ -0,0 +1,759 @@
+use codemap::File;
+use codemap::Pos;
+use codemap::Span;
+use codemap::Spanned;
+use lexer::Fragment;
+use lexer::LexError;
+use lexer::Token;
+use lexer;
+use parser::Node;
+use parser::ParseError;
+use parser::Place;
+use parser::Var;
+use parser;
+use std::cmp;
+use unicode_xid::UnicodeXID;
+
+// A canonical pretty-printer for mask source. It prints the parsed tree rather
+// than patching up the original text, so spacing, parentheses and indentation
+// come out the same however the input was written
+//
+// Comments aren't part of the tree, so they're put back by position: one that
+// ends a line stays at the end of that line, and any other goes on a line of
+// its own before whatever statement or array item came after it. Doc comments
+// go back the same way, which keeps the ones that didn't document anything
+
+const INDENT: &str = "  ";
+
+// arrays, dicts and arguments that would be wider than this are split over
+// several lines
+const MAX_WIDTH: usize = 80;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatError {
+  Lex(LexError),
+  Parse(Vec<ParseError>),
+}
+
+type Body<'n> = Option<&'n [Spanned<Node>]>;
+
+struct Printer<'a> {
+  source: &'a str,
+  base: Pos,
+  // (start, end) offsets of every comment, and the first one not printed yet
+  comments: Vec<(usize, usize)>,
+  next: usize,
+  // (start, end) offsets of every number and string token, in order
+  literals: Vec<(usize, usize)>,
+  out: String,
+  // where the last line printed started or ended in the source, to look for
+  // blank lines after it
+  last: usize,
+  // at the start of a block, where blank lines are dropped
+  fresh: bool,
+  // put a blank line before the next line, whatever the source had
+  want_blank: bool,
+  // split the outermost arrays, dicts and arguments in a statement, because
+  // it's too wide otherwise, and how deep in them we are
+  wrap: bool,
+  depth: usize,
+}
+
+impl<'a> Printer<'a> {
+  fn lo<T>(&self, node: &Spanned<T>) -> usize {
+    (node.span.low() - self.base) as usize
+  }
+
+  fn hi<T>(&self, node: &Spanned<T>) -> usize {
+    (node.span.high() - self.base) as usize
+  }
+
+  fn text<T>(&self, node: &Spanned<T>) -> &'a str {
+    &self.source[self.lo(node)..self.hi(node)]
+  }
+
+  // The source text of the literal token that `node` was parsed from. The
+  // node's own span also covers any parentheses around it
+  fn literal(&self, node: &Spanned<Node>) -> &'a str {
+    let lo = self.lo(node);
+    let k = self.literals.partition_point(|&(x, _)| x < lo);
+    let (lo, hi) = self.literals[k];
+    &self.source[lo..hi]
+  }
+
+  // Whether a part of an interpolated string is its literal text, rather than
+  // a `${...}` that happens to hold a string. Only the second has a token
+  fn is_text(&self, part: &Spanned<Node>) -> bool {
+    let (lo, hi) = (self.lo(part), self.hi(part));
+    let k = self.literals.partition_point(|&(x, _)| x < lo);
+    self.literals.get(k).is_none_or(|&(x, _)| x >= hi)
+  }
+
+  // Whatever's left of `node` after `part`, like the arguments after the
+  // function in a call
+  fn after<T, U>(&self, node: &Spanned<T>, part: &Spanned<U>) -> Span {
+    let len = node.span.high() - node.span.low();
+    node.span.subspan(part.span.high() - node.span.low(), len)
+  }
+
+  fn column(&self, pos: usize) -> usize {
+    pos - self.source[..pos].rfind('\n').map_or(0, |k| k + 1)
+  }
+
+  fn peek_comment(&self) -> Option<(usize, usize)> {
+    self.comments.get(self.next).cloned()
+  }
+
+  fn take_comment(&mut self) -> &'a str {
+    let (lo, hi) = self.comments[self.next];
+    self.next += 1;
+    self.source[lo..hi].trim_end()
+  }
+
+  // Whether there's an empty line in the source between `last` and `pos`
+  fn blank_before(&self, pos: usize) -> bool {
+    match self.source.get(self.last..pos) {
+      Some(gap) => {
+        let lines: Vec<&str> = gap.split('\n').collect();
+        lines.len() > 2
+          && lines[1..lines.len() - 1]
+            .iter()
+            .any(|x| x.trim().is_empty())
+      }
+      None => false,
+    }
+  }
+
+  // Start a new line for whatever's at `pos`, keeping at most one blank line
+  // from the source before it
+  fn start_line(&mut self, pos: usize, indent: usize) {
+    if !self.fresh && (self.want_blank || self.blank_before(pos)) {
+      self.out.push('\n');
+    }
+
+    self.fresh = false;
+    self.want_blank = false;
+    self.last = pos;
+    self.out.push_str(&INDENT.repeat(indent));
+  }
+
+  fn end_line(&mut self, pos: usize) {
+    self.out.push('\n');
+    self.last = cmp::max(self.last, pos);
+  }
+
+  // Print the next comment on a line of its own
+  fn comment_line(&mut self, indent: usize) {
+    let (lo, hi) = self.comments[self.next];
+    self.start_line(lo, indent);
+    let text = self.take_comment();
+    self.out.push_str(text);
+    self.end_line(hi);
+  }
+
+  // Print the comments that come before `pos` on lines of their own
+  fn comments_before(&mut self, pos: usize, indent: usize) {
+    while self.peek_comment().is_some_and(|(lo, _)| lo < pos) {
+      self.comment_line(indent);
+    }
+  }
+
+  // Take the comments to go at the end of the line being printed, which ends
+  // at `pos` in the source: any left inside it, and any after it on the same
+  // line that come before `until`
+  fn trailing(&mut self, pos: usize, until: usize) -> String {
+    let mut text = String::new();
+
+    while let Some((lo, hi)) = self.peek_comment() {
+      let after = lo >= pos && !self.source[pos..lo].contains('\n');
+      if lo >= until || !(lo < pos || after) || self.source[lo..hi].contains('\n') {
+        break;
+      }
+
+      let comment = self.take_comment();
+      text.push(' ');
+      text.push_str(comment);
+
+      // nothing can follow a `#` comment on its line
+      if !comment.starts_with("#[") {
+        break;
+      }
+    }
+
+    text
+  }
+
+  // Print the statements of a block along with the comments around them.
+  // `until` is where whatever follows the block starts
+  fn block(&mut self, body: &[Spanned<Node>], indent: usize, until: usize) {
+    self.fresh = true;
+
+    for (k, stmt) in body.iter().enumerate() {
+      let next = body.get(k + 1).map_or(until, |x| self.lo(x));
+
+      // top-level functions get a blank line on either side
+      if indent == 0 && k > 0 && (is_def(&body[k - 1]) || is_def(stmt)) {
+        self.want_blank = true;
+      }
+
+      self.comments_before(self.lo(stmt), indent);
+      self.stmt(stmt, indent, next);
+    }
+
+    // comments after the last statement that are indented as deep as it is
+    // belong to this block; anything else is left for what comes next
+    let column = body.first().map_or(0, |x| self.column(self.lo(x)));
+    while self
+      .peek_comment()
+      .is_some_and(|(lo, _)| lo < until && self.column(lo) >= column)
+    {
+      self.comment_line(indent);
+    }
+  }
+
+  fn stmt(&mut self, node: &Spanned<Node>, indent: usize, next: usize) {
+    self.start_line(self.lo(node), indent);
+
+    if let Node::If { .. } = node.node {
+      return self.if_stmt(node, indent, next);
+    }
+
+    let start = self.next;
+    let (mut text, mut body) = self.line(node, indent);
+
+    // only the first line still needs indenting
+    let wide = text.lines().enumerate().any(|(k, x)| {
+      let margin = if k == 0 { INDENT.len() * indent } else { 0 };
+      margin + x.chars().count() > MAX_WIDTH
+    });
+
+    if wide {
+      // start over, putting back any comments the first try used up
+      self.next = start;
+      self.wrap = true;
+      let (wrapped, wrapped_body) = self.line(node, indent);
+      self.wrap = false;
+      text = wrapped;
+      body = wrapped_body;
+    }
+
+    self.finish(node, &text, body, indent, next);
+  }
+
+  // The first line of a statement other than an `if`, and its block
+  fn line<'n>(&mut self, node: &'n Spanned<Node>, indent: usize) -> (String, Body<'n>) {
+    match node.node {
+      Node::Assn {
+        ref lhs, ref rhs, ..
+      } => {
+        let lhs = self.place(lhs, indent);
+        let (rhs, body) = self.ml_expr(rhs, indent);
+        (format!("{} = {}", lhs, rhs), body)
+      }
+
+      Node::Decl {
+        ref decl, ref rhs, ..
+      } => {
+        let (rhs, body) = self.ml_expr(rhs, indent);
+        (format!("var {} = {}", var(decl), rhs), body)
+      }
+
+      Node::Save {
+        ref name,
+        rhs: Some(ref rhs),
+        ..
+      } => {
+        let (rhs, body) = self.ml_expr(rhs, indent);
+        (format!("save {} = {}", name, rhs), body)
+      }
+
+      Node::Save {
+        ref name,
+        rhs: None,
+        ..
+      } => (format!("save {}", name), None),
+
+      Node::Return(Some(ref val)) => {
+        let (val, body) = self.ml_expr(val, indent);
+        (format!("return {}", val), body)
+      }
+
+      Node::Return(None) => (String::from("return"), None),
+
+      Node::Stmt(ref expr) => self.ml_expr(expr, indent),
+
+      Node::For {
+        ref decl,
+        ref expr,
+        ref body,
+      } => {
+        let expr = self.expr(expr, indent);
+        (format!("for {} in {}", var(decl), expr), Some(&body[..]))
+      }
+
+      Node::While { ref expr, ref body } => {
+        let expr = self.expr(expr, indent);
+        (format!("while {}", expr), Some(&body[..]))
+      }
+
+      Node::Loop { ref body } => (String::from("loop"), Some(&body[..])),
+
+      Node::Import {
+        ref path,
+        ref alias,
+      } => match *alias {
+        Some(ref alias) => (format!("import {} -> {}", path.join("."), alias), None),
+        None => (format!("import {}", path.join(".")), None),
+      },
+
+      Node::Break => (String::from("break"), None),
+      Node::Continue => (String::from("continue"), None),
+      Node::Pass => (String::from("pass"), None),
+
+      _ => (self.expr(node, indent), None),
+    }
+  }
+
+  // Print an `if` after whatever's already on the line, followed by its
+  // `else if` and `else` blocks
+  fn if_stmt(&mut self, node: &Spanned<Node>, indent: usize, next: usize) {
+    if let Node::If {
+      ref cond,
+      ref body,
+      ref els,
+    } = node.node
+    {
+      let text = format!("if {}", self.expr(cond, indent));
+      let until = els.as_ref().map_or(next, |x| self.lo(x));
+      self.finish(node, &text, Some(body), indent, until);
+
+      if let Some(ref els) = *els {
+        // never a blank line before an `else`
+        self.fresh = true;
+        self.start_line(self.lo(els), indent);
+
+        match els.node {
+          Node::Else { ref body } => self.finish(els, "else", Some(body), indent, next),
+          _ => {
+            self.out.push_str("else ");
+            self.if_stmt(els, indent, next);
+          }
+        }
+      }
+    }
+  }
+
+  // Print the rest of a statement's line, then its block if it has one
+  fn finish(&mut self, node: &Spanned<Node>, text: &str, body: Body, indent: usize, next: usize) {
+    self.out.push_str(text);
+
+    let (lo, hi) = (self.lo(node), self.hi(node));
+    match body {
+      Some(body) if !body.is_empty() => {
+        let trailing = self.trailing(lo, self.lo(&body[0]));
+        self.out.push_str(&trailing);
+        self.end_line(lo);
+        self.block(body, indent + 1, next);
+      }
+
+      // an empty block, like in `while poll();`
+      Some(_) => {
+        self.out.push(';');
+        let trailing = self.trailing(hi, next);
+        self.out.push_str(&trailing);
+        self.end_line(hi);
+      }
+
+      None => {
+        let trailing = self.trailing(hi, next);
+        self.out.push_str(&trailing);
+        self.end_line(hi);
+      }
+    }
+  }
+
+  // An expression that may be followed by a block, like a function
+  fn ml_expr<'n>(&mut self, node: &'n Spanned<Node>, indent: usize) -> (String, Body<'n>) {
+    match node.node {
+      Node::Func {
+        ref params,
+        ref body,
+        ..
+      } => (format!("fn({})", params.join(", ")), Some(body)),
+      Node::Catch(ref body) => (String::from("catch"), Some(body)),
+      _ => (self.expr(node, indent), None),
+    }
+  }
+
+  fn expr(&mut self, node: &Spanned<Node>, indent: usize) -> String {
+    match node.node {
+      Node::Null => String::from("null"),
+      Node::Bool(x) => x.to_string(),
+      // literals are written the way the source had them, so `0xff`,
+      // `1_000` and `r'C:\dir'` keep their spelling
+      Node::Int(_) | Node::Float(_) | Node::Str(_) => self.literal(node).to_string(),
+      Node::Name(ref x) => x.clone(),
+      Node::Table => String::from("table"),
+
+      Node::Interp(ref parts) => {
+        let quote = &self.literal(node)[..1];
+        let mut text = String::from(quote);
+        for part in parts {
+          match part.node {
+            Node::Str(_) if self.is_text(part) => text.push_str(self.text(part)),
+            _ => {
+              let expr = self.expr(part, indent);
+              text.push_str(&format!("${{{}}}", expr));
+            }
+          }
+        }
+        text.push_str(quote);
+        text
+      }
+
+      Node::Index { ref lhs, ref rhs } => {
+        let owner = self.owner(lhs, indent);
+        match rhs.node {
+          Node::Str(ref x) if is_name(x) => format!("{}.{}", owner, x),
+          _ => format!("{}[{}]", owner, self.expr(rhs, indent)),
+        }
+      }
+
+      Node::Method {
+        ref owner,
+        ref method,
+        ref args,
+      } => {
+        let parens = self.after(node, method);
+        let owner = self.owner(owner, indent);
+        let method = match method.node {
+          Node::Str(ref x) => x.clone(),
+          _ => self.expr(method, indent),
+        };
+        format!("{}:{}{}", owner, method, self.args(parens, args, indent))
+      }
+
+      Node::Call { ref func, ref args } => {
+        let parens = self.after(node, func);
+        let func = self.owner(func, indent);
+        format!("{}{}", func, self.args(parens, args, indent))
+      }
+
+      Node::Lambda {
+        ref params,
+        ref expr,
+      } => format!("|{}| {}", params.join(", "), self.expr(expr, indent)),
+
+      Node::Array(ref items) => {
+        let items = items.iter().map(|x| (None, x)).collect();
+        self.list(node.span, "[", "]", items, indent)
+      }
+
+      Node::Dict(ref items) => {
+        let items = items.iter().map(|(k, v)| (Some(k), v)).collect();
+        self.list(node.span, "{", "}", items, indent)
+      }
+
+      Node::BinExpr {
+        ref lhs,
+        ref op,
+        ref rhs,
+      }
+      | Node::LogicExpr {
+        ref lhs,
+        ref op,
+        ref rhs,
+      } => {
+        let lhs = self.operand(lhs, op, false, indent);
+        let rhs = self.operand(rhs, op, true, indent);
+        format!("{} {} {}", lhs, op_text(op), rhs)
+      }
+
+      Node::UnExpr { ref val, ref op } => {
+        let val = match val.node {
+          Node::BinExpr { .. } | Node::LogicExpr { .. } => format!("({})", self.expr(val, indent)),
+          _ => self.expr(val, indent),
+        };
+        format!("{}{}", op_text(op), val)
+      }
+
+      _ => unreachable!("not an expression: {:?}", node.node),
+    }
+  }
+
+  // One side of a binary operator, in parentheses only if the parser would
+  // otherwise group it differently
+  fn operand(&mut self, node: &Spanned<Node>, op: &Token, right: bool, indent: usize) -> String {
+    let text = self.expr(node, indent);
+
+    let inner = match node.node {
+      Node::BinExpr { ref op, .. } | Node::LogicExpr { ref op, .. } => op,
+      _ => return text,
+    };
+
+    let (outer_left, outer_right) = parser::binding_power(op).unwrap_or_default();
+    let (inner_left, inner_right) = parser::binding_power(inner).unwrap_or_default();
+
+    // `a - (b - c)` needs them, `(a - b) - c` doesn't
+    let bare = if right {
+      inner_left >= outer_right
+    } else {
+      inner_right > outer_left
+    };
+
+    if bare {
+      text
+    } else {
+      format!("({})", text)
+    }
+  }
+
+  // The thing being called, indexed or having a method called on it, which
+  // needs parentheses unless it's a single term
+  fn owner(&mut self, node: &Spanned<Node>, indent: usize) -> String {
+    let text = self.expr(node, indent);
+    match node.node {
+      Node::BinExpr { .. }
+      | Node::LogicExpr { .. }
+      | Node::UnExpr { .. }
+      | Node::Int(_)
+      | Node::Float(_) => format!("({})", text),
+      _ => text,
+    }
+  }
+
+  // The arguments to a call, which are split over lines the same way as an
+  // array's items
+  fn args(&mut self, parens: Span, args: &[Spanned<Node>], indent: usize) -> String {
+    let items = args.iter().map(|x| (None, x)).collect();
+    self.list(parens, "(", ")", items, indent)
+  }
+
+  fn place(&mut self, place: &Spanned<Place>, indent: usize) -> String {
+    match place.node {
+      Place::Single(ref node) => self.expr(node, indent),
+      Place::Multi(ref places) => {
+        let places: Vec<String> = places.iter().map(|x| self.place(x, indent)).collect();
+        format!("[{}]", places.join(", "))
+      }
+    }
+  }
+
+  fn key(&mut self, key: &Spanned<Node>, indent: usize) -> String {
+    match key.node {
+      Node::Str(ref x) if is_name(x) => x.clone(),
+      _ => format!("[{}]", self.expr(key, indent)),
+    }
+  }
+
+  fn item(&mut self, key: Option<&Spanned<Node>>, val: &Spanned<Node>, indent: usize) -> String {
+    let val = self.expr(val, indent);
+    match key {
+      Some(key) => format!("{} = {}", self.key(key, indent), val),
+      None => val,
+    }
+  }
+
+  // An array, dict or argument list. It's kept on one line unless it was
+  // split in the source, has comments inside it or is too wide, in which case
+  // each item gets a line of its own and a trailing comma
+  fn list(
+    &mut self,
+    span: Span,
+    open: &str,
+    close: &str,
+    items: Vec<(Option<&Spanned<Node>>, &Spanned<Node>)>,
+    indent: usize,
+  ) -> String {
+    self.depth += 1;
+    let text = self.list_text(span, open, close, items, indent);
+    self.depth -= 1;
+    text
+  }
+
+  fn list_text(
+    &mut self,
+    span: Span,
+    open: &str,
+    close: &str,
+    items: Vec<(Option<&Spanned<Node>>, &Spanned<Node>)>,
+    indent: usize,
+  ) -> String {
+    let lo = (span.low() - self.base) as usize;
+    let hi = (span.high() - self.base) as usize;
+    let inner = INDENT.repeat(indent + 1);
+
+    let commented = self.comments[self.next..]
+      .iter()
+      .any(|&(x, _)| x > lo && x < hi);
+
+    if !commented && !self.source[lo..hi].contains('\n') {
+      let texts: Vec<String> = items
+        .iter()
+        .map(|&(key, val)| self.item(key, val, indent + 1))
+        .collect();
+      let text = format!("{}{}{}", open, texts.join(", "), close);
+
+      let width = INDENT.len() * indent + text.chars().count();
+      let wrap = self.wrap && self.depth == 1 && !items.is_empty();
+      if width <= MAX_WIDTH && !wrap && !text.contains('\n') {
+        return text;
+      }
+
+      let mut text = format!("{}\n", open);
+      for item in texts {
+        text.push_str(&format!("{}{},\n", inner, item));
+      }
+      text.push_str(&INDENT.repeat(indent));
+      text.push_str(close);
+      return text;
+    }
+
+    let mut text = format!("{}\n", open);
+    for (k, &(key, val)) in items.iter().enumerate() {
+      let start = self.lo(key.unwrap_or(val));
+      let until = items
+        .get(k + 1)
+        .map_or(hi, |&(key, val)| self.lo(key.unwrap_or(val)));
+
+      while self.peek_comment().is_some_and(|(x, _)| x < start) {
+        text.push_str(&format!("{}{}\n", inner, self.take_comment()));
+      }
+
+      let item = self.item(key, val, indent + 1);
+      let trailing = self.trailing(self.hi(val), until);
+      text.push_str(&format!("{}{},{}\n", inner, item, trailing));
+    }
+
+    while self.peek_comment().is_some_and(|(x, _)| x < hi) {
+      text.push_str(&format!("{}{}\n", inner, self.take_comment()));
+    }
+
+    text.push_str(&INDENT.repeat(indent));
+    text.push_str(close);
+    text
+  }
+}
+
+// Whether a statement defines a function
+fn is_def(node: &Spanned<Node>) -> bool {
+  let rhs = match node.node {
+    Node::Assn { ref rhs, .. }
+    | Node::Decl { ref rhs, .. }
+    | Node::Save {
+      rhs: Some(ref rhs), ..
+    } => rhs,
+    Node::Stmt(ref expr) => expr,
+    _ => return false,
+  };
+
+  matches!(rhs.node, Node::Func { .. })
+}
+
+fn var(decl: &Spanned<Var>) -> String {
+  match decl.node {
+    Var::Single(ref name) => name.clone(),
+    Var::Multi(ref vars) => {
+      let vars: Vec<String> = vars.iter().map(var).collect();
+      format!("[{}]", vars.join(", "))
+    }
+  }
+}
+
+// Whether `x` can be written as a bare name, like in `a.x` or `{x = 1}`
+fn is_name(x: &str) -> bool {
+  let mut chars = x.chars();
+  chars.next().is_some_and(|c| c == '_' || c.is_xid_start())
+    && chars.all(UnicodeXID::is_xid_continue)
+    && lexer::keyword(x).is_none()
+}
+
+// Operators display in backticks for error messages
+fn op_text(op: &Token) -> String {
+  op.to_string().trim_matches('`').to_string()
+}
+
+// Collect the spans of the number and string tokens in `tokens`, including the
+// ones inside `${...}`
+fn literals(tokens: &[Spanned<Token>], base: Pos, out: &mut Vec<(usize, usize)>) {
+  for tok in tokens {
+    match tok.node {
+      Token::Int(_) | Token::Float(_) | Token::Str(_) => {}
+      Token::Interp(ref parts) => {
+        for part in parts {
+          if let Fragment::Expr(ref tokens) = part.node {
+            literals(tokens, base, out);
+          }
+        }
+      }
+      _ => continue,
+    }
+
+    out.push((
+      (tok.span.low() - base) as usize,
+      (tok.span.high() - base) as usize,
+    ));
+  }
+}
+
+// Format a whole file, which has to lex and parse without errors
+pub fn format(input: &File) -> Result<String, FormatError> {
+  let (tokens, comments) = lexer::lex_lossless(input).map_err(FormatError::Lex)?;
+
+  let mut comments: Vec<Spanned<Token>> = tokens
+    .iter()
+    .filter(|x| matches!(x.node, Token::Doc(_)))
+    .cloned()
+    .chain(comments)
+    .collect();
+  comments.sort_by_key(|x| x.span.low());
+
+  let base = input.span.low();
+  let mut spans = Vec::new();
+  literals(&tokens, base, &mut spans);
+  spans.sort();
+
+  let (root, errors) = parser::parse(tokens).map_err(|err| FormatError::Parse(vec![err]))?;
+  if !errors.is_empty() {
+    return Err(FormatError::Parse(errors));
+  }
+
+  let comments = comments
+    .iter()
+    .map(|x| {
+      (
+        (x.span.low() - base) as usize,
+        (x.span.high() - base) as usize,
+      )
+    })
+    .collect();
+
+  let mut printer = Printer {
+    source: input.source(),
+    base,
+    comments,
+    next: 0,
+    literals: spans,
+    out: String::new(),
+    last: 0,
+    fresh: true,
+    want_blank: false,
+    wrap: false,
+    depth: 0,
+  };
+
+  if let Node::Block(ref body) = root.node {
+    printer.block(body, 0, usize::MAX);
+  }
+
+  // whatever's left is at the end of the file
+  while printer.peek_comment().is_some() {
+    printer.comment_line(0);
+  }
+
+  Ok(printer.out)
+}
+
+#[cfg(test)]
+#[path = "./tests/formatter.rs"]
+mod tests;
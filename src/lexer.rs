@@ -1,11 +1,21 @@
+use codemap::CodeMap;
 use codemap::File;
+use codemap::Span;
 use codemap::Spanned;
+use self::LexErrorKind::*;
 use self::Token::*;
-use std::iter::Enumerate;
+use std::fmt;
 use std::iter::Peekable;
-use std::str::Chars;
+use std::str::CharIndices;
+use unicode_xid::UnicodeXID;
 
-type LexIter<'a> = Peekable<Enumerate<Chars<'a>>>;
+// yields each char along with its byte offset, which is what spans are made of
+type LexIter<'a> = Peekable<CharIndices<'a>>;
+type Lex = Result<Token, LexErrorKind>;
+
+type Tokens = Vec<Spanned<Token>>;
+
+pub type LexError = Spanned<LexErrorKind>;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
@@ -15,8 +25,8 @@ pub enum Token {
   Exit,
   Space,
   End,
-  Tab, // lol
   Comment(String),
+  Doc(String),
 
   // Literals
   Null,
@@ -24,8 +34,8 @@ pub enum Token {
   Float(f64),
   Int(i64),
   Str(String),
+  Interp(Vec<Spanned<Fragment>>),
   Name(String),
-  UnclosedStr(String),
 
   // Keywords
   Break,
@@ -37,6 +47,8 @@ pub enum Token {
   If,
   Import,
   In,
+  LogAnd,
+  LogOr,
   Loop,
   Pass,
   Return,
@@ -63,18 +75,21 @@ pub enum Token {
   Sqr, // ]
 
   // Operators
-  Add, // +
-  And, // &
-  At,  // @
-  Car, // ^
-  Div, // /
-  Dol, // $
-  Mul, // *
-  Neg, // ~
-  Not, // !
-  Or,  // |
-  Pct, // %
-  Sub, // -
+  Add,  // +
+  And,  // &
+  At,   // @
+  Car,  // ^
+  Div,  // /
+  Dol,  // $
+  Fdiv, // //
+  Mul,  // *
+  Neg,  // ~
+  Not,  // !
+  Or,   // |
+  Pct,  // %
+  Shl,  // <<
+  Shr,  // >>
+  Sub,  // -
 
   // Comparisons
   Eql, // ==
@@ -85,31 +100,230 @@ pub enum Token {
   Ne,  // !=
 }
 
-fn lex_number(it: &mut LexIter) -> Token {
-  let mut digits = String::new();
+// A piece of an interpolated string like 'hello ${name}'
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fragment {
+  Str(String),
+  // the tokens between `${` and `}`, followed by a `Cur` for the closing brace
+  Expr(Vec<Spanned<Token>>),
+}
+
+impl fmt::Display for Token {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let sym = match *self {
+      EOF => return write!(f, "end of file"),
+      Enter => return write!(f, "indented block"),
+      Exit => return write!(f, "end of block"),
+      Space => return write!(f, "whitespace"),
+      End => return write!(f, "end of line"),
+      Comment(_) => return write!(f, "comment"),
+      Doc(_) => return write!(f, "doc comment"),
+
+      Null => "null",
+      Bool(true) => "true",
+      Bool(false) => "false",
+      Float(x) => return write!(f, "float `{}`", x),
+      Int(x) => return write!(f, "integer `{}`", x),
+      Str(ref x) => return write!(f, "string {:?}", x),
+      Name(ref x) => return write!(f, "name `{}`", x),
+      Interp(_) => return write!(f, "interpolated string"),
+
+      Break => "break",
+      Catch => "catch",
+      Continue => "continue",
+      Else => "else",
+      For => "for",
+      Func => "fn",
+      If => "if",
+      Import => "import",
+      In => "in",
+      LogAnd => "and",
+      LogOr => "or",
+      Loop => "loop",
+      Pass => "pass",
+      Return => "return",
+      Save => "save",
+      Table => "table",
+      Var => "var",
+      While => "while",
+
+      Arr => "->",
+      Ass => "=",
+      Col => ":",
+      Com => ",",
+      Dot => ".",
+      Meta => "::",
+      Semi => ";",
+
+      Cul => "{",
+      Cur => "}",
+      Pal => "(",
+      Par => ")",
+      Sql => "[",
+      Sqr => "]",
+
+      Add => "+",
+      And => "&",
+      At => "@",
+      Car => "^",
+      Div => "/",
+      Dol => "$",
+      Fdiv => "//",
+      Mul => "*",
+      Neg => "~",
+      Not => "!",
+      Or => "|",
+      Pct => "%",
+      Shl => "<<",
+      Shr => ">>",
+      Sub => "-",
+
+      Eql => "==",
+      Ge => ">=",
+      Gt => ">",
+      Le => "<=",
+      Lt => "<",
+      Ne => "!=",
+    };
+
+    write!(f, "`{}`", sym)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexErrorKind {
+  UnclosedStr,
+  UnclosedComment,
+  UnknownEscape(char),
+  MalformedEscape(String),
+  MalformedNumber(String),
+  IntegerOverflow(String),
+  FloatOverflow(String),
+  UnknownChar(char),
+  UnclosedBracket(Token),
+  MixedIndent,
+  InconsistentIndent(char),
+  BadDedent,
+}
+
+impl fmt::Display for LexErrorKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      UnclosedStr => write!(f, "unterminated string literal"),
+      UnclosedComment => write!(f, "unterminated block comment"),
+      UnknownEscape(c) => write!(f, "unknown escape sequence `\\{}`", c),
+      MalformedEscape(ref x) => write!(f, "malformed escape sequence `{}`", x),
+      MalformedNumber(ref x) => write!(f, "malformed number `{}`", x),
+      IntegerOverflow(ref x) => write!(f, "integer `{}` is too large", x),
+      FloatOverflow(ref x) => write!(f, "float `{}` is too large", x),
+      UnknownChar(c) => write!(f, "unknown character {:?}", c),
+      UnclosedBracket(ref x) => write!(f, "unclosed {}", x),
+      MixedIndent => write!(f, "indentation mixes tabs and spaces"),
+      InconsistentIndent('\t') => write!(f, "indented with tabs, but earlier lines use spaces"),
+      InconsistentIndent(_) => write!(f, "indented with spaces, but earlier lines use tabs"),
+      BadDedent => write!(f, "unindent does not match any outer indentation level"),
+    }
+  }
+}
+
+// Lex a number, which is one of:
+// - an integer in decimal, hex (`0xff`), octal (`0o17`) or binary (`0b1010`)
+// - a decimal float with a fraction and/or an exponent (`1.5`, `1.`, `6.02e23`)
+// Digits can be grouped with `_` as long as there's a digit on either side of
+// it, e.g. `1_000_000`. A float has to start with a digit, so `.5` is a `.`
+// followed by `5`, which leaves `x.0` free to mean indexing
+fn lex_number(it: &mut LexIter) -> Lex {
+  let mut text = String::new();
   while let Some(&(_i, c)) = it.peek() {
-    match c {
-      '0'...'9' | '.' => {
-        it.next();
-        digits.push(c);
-      }
-      _ => break,
+    let sign = (c == '+' || c == '-')
+      && (text.ends_with('e') || text.ends_with('E'))
+      && !text.starts_with("0x");
+
+    if c.is_xid_continue() || c == '.' || sign {
+      it.next();
+      text.push(c);
+    } else {
+      break;
     }
   }
 
-  match digits.contains(".") {
-    true => Float(digits.parse::<f64>().unwrap()),
-    false => Int(digits.parse::<i64>().unwrap()),
+  let radix = match text.get(..2) {
+    Some("0x") => 16,
+    Some("0o") => 8,
+    Some("0b") => 2,
+    _ => return lex_decimal(text),
+  };
+
+  let body = &text[2..];
+  if body.is_empty() || !separated(body, radix) {
+    return Err(MalformedNumber(text));
+  }
+
+  let digits: String = body.chars().filter(|&c| c != '_').collect();
+  match i64::from_str_radix(&digits, radix) {
+    Ok(x) => Ok(Int(x)),
+    Err(_) if digits.chars().all(|c| c.is_digit(radix)) => Err(IntegerOverflow(text)),
+    Err(_) => Err(MalformedNumber(text)),
+  }
+}
+
+fn lex_decimal(text: String) -> Lex {
+  if !separated(&text, 10) {
+    return Err(MalformedNumber(text));
+  }
+
+  let digits: String = text.chars().filter(|&c| c != '_').collect();
+
+  if digits.chars().all(|c| c.is_ascii_digit()) {
+    return digits
+      .parse::<i64>()
+      .map(Int)
+      .map_err(|_| IntegerOverflow(text));
+  }
+
+  // the exponent has to be last, and must have digits of its own
+  let (mantissa, exponent) = match digits.find(['e', 'E']) {
+    Some(k) => (&digits[..k], Some(&digits[k + 1..])),
+    None => (digits.as_str(), None),
+  };
+
+  let valid_mantissa =
+    mantissa.matches('.').count() <= 1 && mantissa.chars().all(|c| c.is_ascii_digit() || c == '.');
+  let valid_exponent = exponent.is_none_or(|exp| {
+    let exp = exp.strip_prefix(['+', '-']).unwrap_or(exp);
+    !exp.is_empty() && exp.chars().all(|c| c.is_ascii_digit())
+  });
+
+  if !valid_mantissa || !valid_exponent {
+    return Err(MalformedNumber(text));
+  }
+
+  match digits.parse::<f64>() {
+    Ok(x) if x.is_infinite() => Err(FloatOverflow(text)),
+    Ok(x) => Ok(Float(x)),
+    Err(_) => Err(MalformedNumber(text)),
   }
 }
 
+// Check that every `_` in a number sits between two digits
+fn separated(body: &str, radix: u32) -> bool {
+  let chars: Vec<char> = body.chars().collect();
+  chars.iter().enumerate().all(|(k, &c)| {
+    c != '_'
+      || (k > 0
+        && k + 1 < chars.len()
+        && chars[k - 1].is_digit(radix)
+        && chars[k + 1].is_digit(radix))
+  })
+}
+
 fn lex_name(it: &mut LexIter) -> Token {
   let mut name = String::new();
   name.push(it.next().unwrap().1);
 
   while let Some(&(_i, c)) = it.peek() {
     match c {
-      'a'...'z' | 'A'...'Z' | '0'...'9' | '_' => {
+      _ if c.is_xid_continue() => {
         it.next();
         name.push(c);
       }
@@ -117,7 +331,12 @@ fn lex_name(it: &mut LexIter) -> Token {
     }
   }
 
-  match name.as_str() {
+  keyword(&name).unwrap_or(Name(name))
+}
+
+// The token for a reserved word, or None if `name` is free to use as a name
+pub fn keyword(name: &str) -> Option<Token> {
+  let tok = match name {
     "true" => Bool(true),
     "false" => Bool(false),
     "null" => Null,
@@ -131,6 +350,8 @@ fn lex_name(it: &mut LexIter) -> Token {
     "if" => If,
     "import" => Import,
     "in" => In,
+    "and" => LogAnd,
+    "or" => LogOr,
     "loop" => Loop,
     "pass" => Pass,
     "return" => Return,
@@ -139,14 +360,28 @@ fn lex_name(it: &mut LexIter) -> Token {
     "var" => Var,
     "while" => While,
 
-    _ => Name(name),
-  }
+    _ => return None,
+  };
+
+  Some(tok)
 }
 
-fn lex_comment(it: &mut LexIter) -> Token {
-  let mut comment = String::new();
+// Lex a comment, which is one of:
+// - `# ...` to the end of the line
+// - `## ...` to the end of the line, documenting whatever comes next. This
+//   only counts when it starts a line outside of any brackets; anywhere else
+//   it's an ordinary comment
+// - `#[ ... ]#` over any number of lines, which can be nested
+fn lex_comment(it: &mut LexIter, line_start: bool) -> Lex {
   it.next();
 
+  if it.next_if(|&(_i, c)| c == '[').is_some() {
+    return lex_block_comment(it);
+  }
+
+  let doc = line_start && it.next_if(|&(_i, c)| c == '#').is_some();
+
+  let mut comment = String::new();
   while let Some(&(_i, c)) = it.peek() {
     match c {
       '\n' => break,
@@ -157,18 +392,66 @@ fn lex_comment(it: &mut LexIter) -> Token {
     }
   }
 
-  Comment(comment)
+  if doc {
+    // `## text` documents "text"
+    let text = comment.strip_prefix(' ').unwrap_or(&comment);
+    return Ok(Doc(text.trim_end().to_string()));
+  }
+
+  Ok(Comment(comment))
 }
 
-fn lex_indent(it: &mut LexIter) -> u64 {
-  let mut indent: u64 = 0;
+fn lex_block_comment(it: &mut LexIter) -> Lex {
+  let mut comment = String::new();
+  let mut depth = 1;
+
+  loop {
+    match it.next() {
+      Some((_i, '#')) if it.next_if(|&(_j, c)| c == '[').is_some() => {
+        depth += 1;
+        comment.push_str("#[");
+      }
+      Some((_i, ']')) if it.next_if(|&(_j, c)| c == '#').is_some() => {
+        depth -= 1;
+        if depth == 0 {
+          return Ok(Comment(comment));
+        }
+        comment.push_str("]#");
+      }
+      Some((_i, c)) => comment.push(c),
+      None => return Err(UnclosedComment),
+    }
+  }
+}
+
+// Whether the rest of the line is a comment, but not a doc comment
+fn comment_only(it: &LexIter) -> bool {
+  let mut ahead = it.clone();
+  ahead.next();
+
+  match ahead.next() {
+    Some((_i, '#')) => false,
+    Some((_i, '[')) => {
+      if lex_block_comment(&mut ahead).is_err() {
+        return false;
+      }
+      let rest = ahead.find(|&(_i, c)| c != ' ' && c != '\t' && c != '\r');
+      matches!(rest, None | Some((_, '\n')))
+    }
+    _ => true,
+  }
+}
+
+// Consume a line break and the whitespace after it, returning that whitespace
+fn lex_indent(it: &mut LexIter) -> String {
+  let mut indent = String::new();
   it.next();
 
   while let Some(&(_i, c)) = it.peek() {
     match c {
-      ' ' => {
+      ' ' | '\t' => {
         it.next();
-        indent += 1;
+        indent.push(c);
       }
       _ => break,
     }
@@ -177,13 +460,38 @@ fn lex_indent(it: &mut LexIter) -> u64 {
   indent
 }
 
-fn lex_pair(next: char, solo: Token, pair: Token, it: &mut LexIter) -> Token {
+// Measure a line's indentation, where a tab and a space are both one column.
+// A file has to stick to one or the other, since there's no sane way to
+// compare a line indented with tabs to one indented with spaces
+fn measure_indent(indent: &str, style: &mut Option<char>) -> Result<u64, LexErrorKind> {
+  let c = match indent.chars().next() {
+    Some(c) => c,
+    None => return Ok(0),
+  };
+
+  if indent.chars().any(|x| x != c) {
+    return Err(MixedIndent);
+  }
+
+  match *style {
+    Some(x) if x != c => return Err(InconsistentIndent(c)),
+    _ => *style = Some(c),
+  }
+
+  Ok(indent.len() as u64)
+}
+
+// Lex a symbol that may be followed by a second character to form a different
+// token, e.g. `<`, `<=` and `<<`
+fn lex_pair(solo: Token, pairs: &[(char, Token)], it: &mut LexIter) -> Token {
   it.next();
 
   if let Some(&(_i, c)) = it.peek() {
-    if c == next {
-      it.next();
-      return pair;
+    for &(next, ref pair) in pairs {
+      if c == next {
+        it.next();
+        return pair.clone();
+      }
     }
   }
 
@@ -192,113 +500,311 @@ fn lex_pair(next: char, solo: Token, pair: Token, it: &mut LexIter) -> Token {
   solo
 }
 
-fn lex_string(it: &mut LexIter) -> Token {
+// Lex a string quoted with either ' or ". Raw strings like r'C:\dir' take
+// every character literally, so they can't contain their own quote
+//
+// Other strings can embed expressions as in 'hello ${name}', which makes them
+// an Interp instead of a Str. Errors inside an embedded expression already
+// have a span of their own, so this returns a whole LexError
+fn lex_string(input: &File, it: &mut LexIter, raw: bool) -> Result<Token, LexError> {
   let mut contents = String::new();
-  let mut escaped = false;
-  it.next();
+  let mut parts: Vec<Spanned<Fragment>> = Vec::new();
 
-  loop {
-    if let Some(&(_i, c)) = it.peek() {
-      if escaped {
-        match c {
-          'n' => {
-            it.next();
-            contents.push('\n');
-          }
-          'r' => {
-            it.next();
-            contents.push('\r');
-          }
-          't' => {
-            it.next();
-            contents.push('\t');
-          }
-          _ => {
-            it.next();
-            contents.push(c);
-          }
+  let start = it.peek().map_or(input.source().len(), |&(i, _)| i);
+  if raw {
+    it.next();
+  }
+
+  let (mut chunk_start, quote) = match it.next() {
+    Some((i, c)) => (i + c.len_utf8(), c),
+    None => return Err(error_at(input, it, start, UnclosedStr)),
+  };
+
+  let end = loop {
+    match it.next() {
+      Some((i, c)) if c == quote => break i,
+      Some((_i, '\\')) if !raw => match lex_escape(it) {
+        Ok(c) => contents.push(c),
+        Err(kind) => return Err(error_at(input, it, start, kind)),
+      },
+
+      Some((i, '$')) if !raw && it.next_if(|&(_j, c)| c == '{').is_some() => {
+        if !contents.is_empty() {
+          parts.push(Spanned {
+            node: Fragment::Str(contents.clone()),
+            span: input.span.subspan(chunk_start as u64, i as u64),
+          });
+          contents.clear();
         }
-        escaped = false;
-      } else {
-        match c {
-          '\'' => {
-            it.next();
-            break;
-          }
-          '\\' => {
-            it.next();
-            escaped = true;
-          }
-          _ => {
+
+        let (tokens, end) = lex_interp(input, it, start, i + 2)?;
+        parts.push(Spanned {
+          node: Fragment::Expr(tokens),
+          span: input.span.subspan(i as u64, end as u64),
+        });
+        chunk_start = end;
+      }
+
+      Some((_i, c)) => contents.push(c),
+      None => return Err(error_at(input, it, start, UnclosedStr)),
+    }
+  };
+
+  if parts.is_empty() {
+    return Ok(Str(contents));
+  }
+
+  if !contents.is_empty() {
+    parts.push(Spanned {
+      node: Fragment::Str(contents),
+      span: input.span.subspan(chunk_start as u64, end as u64),
+    });
+  }
+
+  Ok(Interp(parts))
+}
+
+// Lex the expression in a `${...}`, starting just after the `{`, in the string
+// that starts at `quote`. Returns its tokens and the offset just past the
+// closing `}`
+fn lex_interp(
+  input: &File,
+  it: &mut LexIter,
+  quote: usize,
+  start: usize,
+) -> Result<(Vec<Spanned<Token>>, usize), LexError> {
+  // find the closing brace, skipping over any strings inside the expression
+  // since they could have braces of their own
+  let mut depth = 0;
+  let close = loop {
+    match it.next() {
+      Some((_i, '{')) => depth += 1,
+      Some((i, '}')) if depth == 0 => break i,
+      Some((_i, '}')) => depth -= 1,
+      Some((_i, q)) if q == '\'' || q == '"' => loop {
+        match it.next() {
+          Some((_j, '\\')) => {
             it.next();
-            contents.push(c);
           }
+          Some((_j, c)) if c == q => break,
+          Some(_) => {}
+          None => return Err(error_at(input, it, quote, UnclosedStr)),
         }
+      },
+      Some(_) => {}
+      None => return Err(error_at(input, it, quote, UnclosedStr)),
+    }
+  };
+
+  // lex it as a file of its own, then move the spans back to where they came
+  // from in this one
+  let mut map = CodeMap::new();
+  let file = map.add_file(String::new(), input.source()[start..close].to_string());
+  let shift = |span: Span| {
+    let lo = span.low() - file.span.low();
+    let hi = span.high() - file.span.low();
+    input.span.subspan(start as u64 + lo, start as u64 + hi)
+  };
+
+  let mut tokens = lex(&file).map_err(|err| Spanned {
+    span: shift(err.span),
+    node: err.node,
+  })?;
+
+  // drop the End and EOF that finish every file
+  tokens.pop();
+  if tokens.last().is_some_and(|tok| tok.node == End) {
+    tokens.pop();
+  }
+
+  let mut tokens: Vec<Spanned<Token>> = tokens
+    .into_iter()
+    .map(|tok| Spanned {
+      span: shift(tok.span),
+      node: tok.node,
+    })
+    .collect();
+
+  tokens.push(Spanned {
+    node: Cur,
+    span: input.span.subspan(close as u64, close as u64 + 1),
+  });
+
+  Ok((tokens, close + 1))
+}
+
+// Give an error the span from `start` to wherever the lexer got to, the same
+// as the main loop does for a token that fails
+fn error_at(input: &File, it: &mut LexIter, start: usize, kind: LexErrorKind) -> LexError {
+  let end = it.peek().map_or(input.source().len(), |&(i, _)| i);
+  Spanned {
+    node: kind,
+    span: input.span.subspan(start as u64, end as u64),
+  }
+}
+
+// Lex the rest of an escape sequence, after the backslash
+fn lex_escape(it: &mut LexIter) -> Result<char, LexErrorKind> {
+  let c = match it.next() {
+    Some((_i, c)) => c,
+    None => return Err(UnclosedStr),
+  };
+
+  match c {
+    'n' => Ok('\n'),
+    'r' => Ok('\r'),
+    't' => Ok('\t'),
+    '0' => Ok('\0'),
+    '\\' | '\'' | '"' | '$' => Ok(c),
+
+    // exactly two hex digits, and only up to 0x7f; anything higher is a
+    // UTF-8 byte rather than a char, which is what \u{..} is for
+    'x' => {
+      let hex: String = it.by_ref().take(2).map(|(_i, c)| c).collect();
+      match u8::from_str_radix(&hex, 16) {
+        Ok(x) if x < 0x80 && hex.chars().all(|c| c.is_ascii_hexdigit()) => Ok(x as char),
+        _ => Err(MalformedEscape(format!("\\x{}", hex))),
       }
-    } else {
-      // this is kind of an escape hatch because I didn't think through the
-      // lexer it hadn't occurred to me at the time that lexing a token could
-      // fail, so the lexer has no functionality to support failure. oh well.
-      return UnclosedStr(contents);
     }
+
+    // one to six hex digits in braces, naming a Unicode scalar value
+    'u' => {
+      if it.next_if(|&(_i, c)| c == '{').is_none() {
+        return Err(MalformedEscape(String::from("\\u")));
+      }
+
+      let mut hex = String::new();
+      loop {
+        match it.next() {
+          Some((_i, '}')) => break,
+          Some((_i, c)) if c.is_ascii_hexdigit() && hex.len() < 6 => hex.push(c),
+          _ => return Err(MalformedEscape(format!("\\u{{{}", hex))),
+        }
+      }
+
+      u32::from_str_radix(&hex, 16)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or_else(|| MalformedEscape(format!("\\u{{{}}}", hex)))
+    }
+
+    _ => Err(UnknownEscape(c)),
   }
+}
 
-  Str(contents)
+pub fn lex(input: &File) -> Result<Vec<Spanned<Token>>, LexError> {
+  lex_lossless(input).map(|(tokens, _comments)| tokens)
 }
 
-pub fn lex(input: &File) -> Vec<Spanned<Token>> {
+// Like `lex`, but also returns the comments it skipped over. Everything else
+// between two tokens is whitespace, so together they account for every byte
+// of the source
+pub fn lex_lossless(input: &File) -> Result<(Tokens, Tokens), LexError> {
   let mut tokens: Vec<Spanned<Token>> = Vec::new();
-  let mut it: LexIter = input.source().chars().enumerate().peekable();
+  let mut comments: Vec<Spanned<Token>> = Vec::new();
+  let mut it: LexIter = input.source().char_indices().peekable();
   let mut indent_stack: Vec<u64> = Vec::new();
   let mut current_indent: u64 = 0;
 
+  // whether the file is indented with tabs or spaces, once we've seen either
+  let mut indent_style: Option<char> = None;
+
+  // brackets that are still open; line breaks inside them don't count
+  let mut brackets: Vec<Spanned<Token>> = Vec::new();
+
   // start at indentation 0
   indent_stack.push(current_indent);
 
   while let Some(&(i, c)) = it.peek() {
     let x = if current_indent < indent_stack[indent_stack.len() - 1] {
       indent_stack.pop();
-      Exit
+      Ok(Exit)
     } else {
       match c {
-        '#' => lex_comment(&mut it),
-        'a'...'z' | 'A'...'Z' | '_' => lex_name(&mut it),
-        '0'...'9' => lex_number(&mut it),
+        '#' => {
+          let line = input.source()[..i].rsplit('\n').next().unwrap_or("");
+          lex_comment(&mut it, brackets.is_empty() && line.trim().is_empty())
+        }
+        // raw strings start out looking like a name
+        'r' if matches!(it.clone().nth(1), Some((_, '\'')) | Some((_, '"'))) => {
+          Ok(lex_string(input, &mut it, true)?)
+        }
+        '_' => Ok(lex_name(&mut it)),
+        _ if c.is_xid_start() => Ok(lex_name(&mut it)),
+        '0'..='9' => lex_number(&mut it),
+        '\n' if !brackets.is_empty() => {
+          lex_indent(&mut it);
+          Ok(Space)
+        }
         '\n' => {
           let indent = lex_indent(&mut it);
 
-          if let Some(&(_, c)) = it.peek() {
+          if let Some(&(j, c)) = it.peek() {
+            // if this panics, there's a bug - indent_stack should always have a 0
+            let top = indent_stack[indent_stack.len() - 1];
+
             match c {
-              '\n' => Space,
+              // blank lines and lines with only a comment on them don't
+              // change the indentation, but doc comments do since the parser
+              // sees them
+              '\n' => Ok(Space),
+              '#' if comment_only(&it) => Ok(Space),
               _ => {
-                current_indent = indent;
-                // if this panics, there's a bug - indent_stack should always have a 0
-                if indent > indent_stack[indent_stack.len() - 1] {
-                  indent_stack.push(indent);
-                  Enter
-                } else {
-                  End
+                // indentation errors point at the whole line rather than
+                // just its indentation, or the line break before it
+                let line = || {
+                  let end = it
+                    .clone()
+                    .find(|&(_, c)| c == '\n')
+                    .map_or(input.source().len(), |(k, _)| k);
+                  input.span.subspan(j as u64, end as u64)
+                };
+
+                match measure_indent(&indent, &mut indent_style) {
+                  // a dedent has to land exactly on an enclosing level
+                  Ok(indent) if indent < top && !indent_stack.contains(&indent) => {
+                    return Err(Spanned {
+                      node: BadDedent,
+                      span: line(),
+                    });
+                  }
+                  Ok(indent) => {
+                    current_indent = indent;
+                    if indent > top {
+                      indent_stack.push(indent);
+                      Ok(Enter)
+                    } else {
+                      Ok(End)
+                    }
+                  }
+                  Err(kind) => {
+                    return Err(Spanned {
+                      node: kind,
+                      span: line(),
+                    })
+                  }
                 }
               }
             }
           } else {
-            End
+            Ok(End)
           }
         }
-        '\t' => {
+        ' ' | '\t' | '\r' => {
           it.next();
-          Tab
+          Ok(Space)
         }
 
-        '\'' => lex_string(&mut it),
+        '\'' | '"' => Ok(lex_string(input, &mut it, false)?),
 
         // Compound
-        '-' => lex_pair('>', Sub, Arr, &mut it),
-        '<' => lex_pair('=', Lt, Le, &mut it),
-        '>' => lex_pair('=', Gt, Ge, &mut it),
-        '=' => lex_pair('=', Ass, Eql, &mut it),
-        '!' => lex_pair('=', Not, Ne, &mut it),
-        ':' => lex_pair(':', Col, Meta, &mut it),
+        '-' => Ok(lex_pair(Sub, &[('>', Arr)], &mut it)),
+        '<' => Ok(lex_pair(Lt, &[('=', Le), ('<', Shl)], &mut it)),
+        '>' => Ok(lex_pair(Gt, &[('=', Ge), ('>', Shr)], &mut it)),
+        '=' => Ok(lex_pair(Ass, &[('=', Eql)], &mut it)),
+        '!' => Ok(lex_pair(Not, &[('=', Ne)], &mut it)),
+        ':' => Ok(lex_pair(Col, &[(':', Meta)], &mut it)),
+        '/' => Ok(lex_pair(Div, &[('/', Fdiv)], &mut it)),
 
         // Symbols
         // -> Arr
@@ -306,141 +812,143 @@ pub fn lex(input: &File) -> Vec<Spanned<Token>> {
         // : Col
         ',' => {
           it.next();
-          Com
+          Ok(Com)
         }
         '.' => {
           it.next();
-          Dot
+          Ok(Dot)
         }
         // :: Meta
         ';' => {
           it.next();
-          Semi
+          Ok(Semi)
         }
 
         // Braces
         '(' => {
           it.next();
-          Pal
+          Ok(Pal)
         }
         ')' => {
           it.next();
-          Par
+          Ok(Par)
         }
         '[' => {
           it.next();
-          Sql
+          Ok(Sql)
         }
         ']' => {
           it.next();
-          Sqr
+          Ok(Sqr)
         }
         '{' => {
           it.next();
-          Cul
+          Ok(Cul)
         }
         '}' => {
           it.next();
-          Cur
+          Ok(Cur)
         }
 
         // Operators
         '+' => {
           it.next();
-          Add
+          Ok(Add)
         }
         '&' => {
           it.next();
-          And
+          Ok(And)
         }
         '@' => {
           it.next();
-          At
+          Ok(At)
         }
         '^' => {
           it.next();
-          Car
-        }
-        '/' => {
-          it.next();
-          Div
+          Ok(Car)
         }
+        // / Div
         '$' => {
           it.next();
-          Dol
+          Ok(Dol)
         }
         '*' => {
           it.next();
-          Mul
+          Ok(Mul)
         }
         '~' => {
           it.next();
-          Neg
+          Ok(Neg)
         }
         // ! Not
         '|' => {
           it.next();
-          Or
+          Ok(Or)
         }
         '%' => {
           it.next();
-          Pct
+          Ok(Pct)
         }
         // - Sub
         _ => {
           it.next();
-          Space
+          Err(UnknownChar(c))
         }
       }
     };
 
     // figure out what the span was for this token
     // either there's something we can peek, or the span is until EOF
-    let mut end_i = if let Some(&(j, _)) = it.peek() {
+    let end_i = if let Some(&(j, _)) = it.peek() {
       j
     } else {
       input.source().len()
     };
     let span = input.span.subspan(i as u64, end_i as u64);
 
+    let x = match x {
+      Ok(x) => x,
+      Err(kind) => return Err(Spanned { node: kind, span }),
+    };
+
+    match x {
+      Pal | Sql | Cul => brackets.push(Spanned {
+        node: x.clone(),
+        span,
+      }),
+      // a stray closing bracket is left for the parser to complain about
+      Par | Sqr | Cur => {
+        brackets.pop();
+      }
+      _ => (),
+    }
+
     match x {
       // don't emit tokens for spaces or comments
       Space => (),
-      Comment(_) => (),
+      Comment(_) => comments.push(Spanned { node: x, span }),
 
       // don't insert duplicate newlines, or file-leading newlines
-      End => match tokens.last().cloned() {
-        None => (),
-        Some(x) => {
-          if x.node != End {
-            tokens.push(Spanned {
-              node: End,
-              span: span,
-            });
-          }
-        }
+      End => match tokens.last() {
+        Some(x) if x.node != End => tokens.push(Spanned { node: End, span }),
+        _ => (),
       },
 
       // exit should always be followed by a End
       Exit => {
-        tokens.push(Spanned {
-          node: Exit,
-          span: span,
-        });
-        tokens.push(Spanned {
-          node: End,
-          span: span,
-        });
+        tokens.push(Spanned { node: Exit, span });
+        tokens.push(Spanned { node: End, span });
       }
 
       // emit everything else
-      _ => tokens.push(Spanned {
-        node: x,
-        span: span,
-      }),
+      _ => tokens.push(Spanned { node: x, span }),
     }
   }
 
+  if let Some(open) = brackets.pop() {
+    return Err(open.map_node(UnclosedBracket));
+  }
+
   // make a span for all closing tokens
   let end = input.source().len() as u64;
   let span = input.span.subspan(end, end);
@@ -448,33 +956,21 @@ pub fn lex(input: &File) -> Vec<Spanned<Token>> {
   // sometimes a trailing newline goes missing before EOF
   if let Some(x) = tokens.last().cloned() {
     if x.node != End {
-      tokens.push(Spanned {
-        node: End,
-        span: span,
-      });
+      tokens.push(Spanned { node: End, span });
     }
   }
 
   // exit blocks that are open at EOF
   while indent_stack.len() > 1 {
-    tokens.push(Spanned {
-      node: Exit,
-      span: span,
-    });
-    tokens.push(Spanned {
-      node: End,
-      span: span,
-    });
+    tokens.push(Spanned { node: Exit, span });
+    tokens.push(Spanned { node: End, span });
     indent_stack.pop();
   }
 
   // push the EOF token
-  tokens.push(Spanned {
-    node: EOF,
-    span: span,
-  });
+  tokens.push(Spanned { node: EOF, span });
 
-  tokens
+  Ok((tokens, comments))
 }
 
 #[cfg(test)]
@@ -1,4 +1,7 @@
 extern crate codemap;
+extern crate unicode_xid;
+pub mod cst;
+pub mod formatter;
 pub mod lexer;
 pub mod parser;
 pub mod semck;
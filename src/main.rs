@@ -4,9 +4,15 @@ extern crate mask;
 
 use clap::App;
 use clap::Arg;
+use clap::SubCommand;
 use codemap::CodeMap;
+use codemap::Span;
+use mask::formatter::FormatError;
+use mask::formatter;
+use mask::lexer::LexErrorKind;
 use mask::lexer::Token;
 use mask::lexer;
+use mask::parser::ParseError;
 use mask::parser::ParseErrorKind;
 use mask::parser;
 use mask::semck;
@@ -15,8 +21,84 @@ use std::io::Write;
 use std::io::prelude::*;
 use std::io;
 use std::path::Path;
+use std::process;
 
-fn print_tokens(map: &CodeMap, tokens: &Vec<codemap::Spanned<lexer::Token>>) {
+// Describe an error, pointing at where it happened if we know
+fn error_message(map: &CodeMap, message: &str, span: Option<Span>) -> String {
+  match span {
+    Some(span) => {
+      let loc = map.look_up_pos(span.low());
+      let line = loc.position.line;
+
+      // codemap counts columns in bytes, but people count them in characters
+      let column = loc.file.source_line(line)[..loc.position.column]
+        .chars()
+        .count();
+
+      format!(
+        "{} at {}:{}:{}",
+        message,
+        loc.file.name(),
+        line + 1,
+        column + 1
+      )
+    }
+    None => message.to_string(),
+  }
+}
+
+fn fail(map: &CodeMap, message: &str, span: Option<Span>) -> ! {
+  eprintln!("{}", error_message(map, message, span));
+  process::exit(1);
+}
+
+fn fail_parse(map: &CodeMap, errors: &[ParseError]) -> ! {
+  for why in errors {
+    eprintln!("{}", error_message(map, &why.message, why.span));
+  }
+  process::exit(1);
+}
+
+// Format each file in place, or with `check`, only list the ones that aren't
+// formatted and fail if there are any
+fn format_files(map: &mut CodeMap, paths: Vec<&str>, check: bool) {
+  let mut unformatted = false;
+
+  for filename in paths {
+    let path = Path::new(filename);
+    let mut contents = String::new();
+    if let Err(why) = File::open(path).and_then(|mut file| file.read_to_string(&mut contents)) {
+      panic!("Couldn't read {}: {}", path.display(), why);
+    }
+
+    let file = map.add_file(filename.to_string(), contents.clone());
+    let formatted = match formatter::format(&file) {
+      Ok(formatted) => formatted,
+      Err(FormatError::Lex(why)) => fail(map, &why.node.to_string(), Some(why.span)),
+      Err(FormatError::Parse(errors)) => fail_parse(map, &errors),
+    };
+
+    if formatted == contents {
+      continue;
+    }
+
+    if check {
+      println!("{} is not formatted", path.display());
+      unformatted = true;
+    } else if let Err(why) =
+      File::create(path).and_then(|mut file| file.write_all(formatted.as_bytes()))
+    {
+      panic!("Couldn't write {}: {}", path.display(), why);
+    }
+  }
+
+  if unformatted {
+    process::exit(1);
+  }
+}
+
+#[allow(dead_code)]
+fn print_tokens(_map: &CodeMap, tokens: &[codemap::Spanned<lexer::Token>]) {
   let mut indent = 0;
 
   for token in tokens {
@@ -42,8 +124,8 @@ fn print_tokens(map: &CodeMap, tokens: &Vec<codemap::Spanned<lexer::Token>>) {
       }
 
       _ => {
-        let span = map.look_up_span(token.span);
         print!("{:?} ", token.node);
+        //let span = map.look_up_span(token.span);
         //print!("{}:{}:{}: {:?} ", span.file.name(), span.begin.line, span.begin.column, token.node);
         //print!("{:?}<{:?}> ", token.node, );
       }
@@ -70,30 +152,48 @@ fn main() {
         .index(1)
         .help("Mask module to execute"),
     )
+    .subcommand(
+      SubCommand::with_name("fmt")
+        .about("Formats mask modules in place")
+        .arg(Arg::with_name("check").long("check").help(
+          "Lists modules that aren't formatted, and fails if there are any, without changing them",
+        ))
+        .arg(
+          Arg::with_name("path")
+            .required(true)
+            .multiple(true)
+            .index(1)
+            .help("Mask modules to format"),
+        ),
+    )
     .get_matches();
 
   let mut map = CodeMap::new();
 
-  if let Some(source) = argv.value_of("code") {
+  if let Some(fmt_argv) = argv.subcommand_matches("fmt") {
+    let paths = fmt_argv.values_of("path").unwrap().collect();
+    format_files(&mut map, paths, fmt_argv.is_present("check"));
+  } else if let Some(source) = argv.value_of("code") {
     let file = map.add_file(String::from("_stdin"), source.to_string());
 
     // FIXME this code is duplicated a lot, but that's because there's no
     // "module" component in the compiler yet
-    let tokens = lexer::lex(&file);
-    let ast = parser::parse(tokens);
-
-    match ast {
-      Ok(mut root) => {
-        {
-          let mut ck = semck::SemChecker::new();
-          ck.check(&mut root);
-        }
-        println!("Checked: {:?}", root);
-      }
-      Err(why) => {
-        panic!("Couldn't semck: {:?}", why);
-      }
+    let tokens = match lexer::lex(&file) {
+      Ok(tokens) => tokens,
+      Err(why) => fail(&map, &why.node.to_string(), Some(why.span)),
     };
+    let (mut root, errors) = parser::parse(tokens).unwrap_or_else(|why| fail_parse(&map, &[why]));
+    if !errors.is_empty() {
+      fail_parse(&map, &errors);
+    }
+
+    {
+      let mut ck = semck::SemChecker::new();
+      if let Err(why) = ck.check(&mut root) {
+        fail(&map, &why.node.to_string(), Some(why.span));
+      }
+    }
+    println!("Checked: {:?}", root);
   } else if let Some(filename) = argv.value_of("path") {
     let path = Path::new(&filename);
     let mut file = match File::open(path) {
@@ -108,16 +208,18 @@ fn main() {
     };
 
     // see FIXME above
-    let tokens = lexer::lex(&cm_file);
-    let mut ast = match parser::parse(tokens) {
-      Ok(root) => root,
-      Err(why) => panic!("Couldn't semck: {:?}", why),
+    let tokens = match lexer::lex(&cm_file) {
+      Ok(tokens) => tokens,
+      Err(why) => fail(&map, &why.node.to_string(), Some(why.span)),
     };
+    let (mut ast, errors) = parser::parse(tokens).unwrap_or_else(|why| fail_parse(&map, &[why]));
+    if !errors.is_empty() {
+      fail_parse(&map, &errors);
+    }
 
     let mut ck = semck::SemChecker::new();
-    match ck.check(&mut ast) {
-      Err(why) => panic!("Bad semck: {:?}", why),
-      _ => {}
+    if let Err(why) = ck.check(&mut ast) {
+      fail(&map, &why.node.to_string(), Some(why.span));
     }
 
     println!("Checked: {:?}", ast);
@@ -135,19 +237,19 @@ fn main() {
 
     loop {
       let mut buffer = String::new();
-      if wait_for_blank {
+      if !chunk.is_empty() {
         print!(". ");
       } else {
         print!("> ");
       }
-      io::stdout().flush();
+      io::stdout().flush().unwrap();
 
       match io::stdin().read_line(&mut buffer) {
         Ok(nbytes) => {
           chunk.push_str(&buffer);
 
           if nbytes == 0 || chunk == "quit\n" {
-            println!("");
+            println!();
             break;
           }
 
@@ -157,18 +259,63 @@ fn main() {
 
           let file = map.add_file(String::from("_stdin"), chunk.clone());
 
-          let tokens = lexer::lex(&file);
-          let ast = parser::parse(tokens);
-          match ast {
+          let tokens = match lexer::lex(&file) {
+            Ok(tokens) => tokens,
+
+            // unclosed string - wait for the rest of it
+            Err(ref why) if why.node == LexErrorKind::UnclosedStr => {
+              wait_for_blank = true;
+              continue;
+            }
+
+            // open bracket or block comment - keep reading lines until it's closed
+            Err(codemap::Spanned {
+              node: LexErrorKind::UnclosedBracket(_),
+              ..
+            })
+            | Err(codemap::Spanned {
+              node: LexErrorKind::UnclosedComment,
+              ..
+            }) => continue,
+
+            Err(why) => {
+              let message = why.node.to_string();
+              println!("{}", error_message(&map, &message, Some(why.span)));
+              chunk.clear();
+              wait_for_blank = false;
+              continue;
+            }
+          };
+
+          let (ast, errors) = match parser::parse(tokens) {
+            Ok(parsed) => parsed,
+            Err(why) => {
+              println!("{}", error_message(&map, &why.message, why.span));
+              chunk.clear();
+              wait_for_blank = false;
+              continue;
+            }
+          };
+          match errors.last() {
             // incomplete statement - say we're waiting for an empty line and then skip the rest
-            Err(ParseErrorKind::UnexpectedToken(Token::End))
-            | Err(ParseErrorKind::UnexpectedEOF) => {
+            Some(why)
+              if why.kind == ParseErrorKind::UnexpectedToken(Token::End)
+                || why.kind == ParseErrorKind::UnexpectedEOF =>
+            {
               wait_for_blank = true;
               continue;
             }
 
+            Some(_) => {
+              for why in &errors {
+                println!("{}", error_message(&map, &why.message, why.span));
+              }
+              chunk.clear();
+              wait_for_blank = false;
+            }
+
             // we have a complete statement! parse it!
-            _ => {
+            None => {
               println!("AST: {:?}", ast);
               chunk.clear();
               wait_for_blank = false;
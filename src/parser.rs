@@ -1,105 +1,185 @@
+use codemap::Span;
 use codemap::Spanned;
+use lexer::Fragment;
 use lexer::Token;
+use std::fmt;
 use std::iter::Peekable;
 use std::slice::Iter;
 use self::ParseErrorKind::*;
 
-type ParseIter<'a> = Peekable<Iter<'a, Spanned<Token>>>;
-type Parse = Result<Node, ParseErrorKind>;
+type Parse = Result<Spanned<Node>, ParseError>;
+
+// A token stream that also collects the errors recovered from along the way
+#[derive(Clone)]
+struct ParseIter<'a> {
+  tokens: Peekable<Iter<'a, Spanned<Token>>>,
+  errors: Vec<ParseError>,
+  consumed: usize,
+  // the span of the last token taken that wasn't a line break or indentation
+  last: Option<Span>,
+}
+
+impl<'a> ParseIter<'a> {
+  fn new(tokens: &'a [Spanned<Token>]) -> ParseIter<'a> {
+    ParseIter {
+      tokens: tokens.iter().peekable(),
+      errors: Vec::new(),
+      consumed: 0,
+      last: None,
+    }
+  }
+
+  fn peek(&mut self) -> Option<&&'a Spanned<Token>> {
+    self.tokens.peek()
+  }
+
+  // Look one token past `peek()`
+  fn peek_second(&self) -> Option<&'a Spanned<Token>> {
+    let mut ahead = self.tokens.clone();
+    ahead.next();
+    ahead.next()
+  }
+}
+
+impl<'a> Iterator for ParseIter<'a> {
+  type Item = &'a Spanned<Token>;
+
+  fn next(&mut self) -> Option<&'a Spanned<Token>> {
+    let tok = self.tokens.next();
+    if let Some(tok) = tok {
+      self.consumed += 1;
+      match tok.node {
+        Token::End | Token::Enter | Token::Exit | Token::EOF => {}
+        _ => self.last = Some(tok.span),
+      }
+    }
+    tok
+  }
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Var {
   Single(String),
-  Multi(Vec<Var>),
+  Multi(Vec<Spanned<Var>>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Place {
-  Single(Box<Node>),
-  Multi(Vec<Place>),
+  Single(Box<Spanned<Node>>),
+  Multi(Vec<Spanned<Place>>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Node {
-  Block(Vec<Node>),
-  Stmt(Box<Node>),
-  Catch(Vec<Node>),
+  Block(Vec<Spanned<Node>>),
+  Stmt(Box<Spanned<Node>>),
+  Catch(Vec<Spanned<Node>>),
+  // `doc` holds any `##` comment lines right before the statement
   Assn {
-    lhs: Place,
-    rhs: Box<Node>,
+    lhs: Spanned<Place>,
+    rhs: Box<Spanned<Node>>,
+    doc: Option<String>,
   },
-  If {
-    cond: Box<Node>,
-    body: Vec<Node>,
-    els: Option<Box<Node>>,
+  Decl {
+    decl: Spanned<Var>,
+    rhs: Box<Spanned<Node>>,
+    doc: Option<String>,
   },
-  ElseIf {
-    cond: Box<Node>,
-    body: Vec<Node>,
+  // `els` is another If for `else if`, or an Else
+  If {
+    cond: Box<Spanned<Node>>,
+    body: Vec<Spanned<Node>>,
+    els: Option<Box<Spanned<Node>>>,
   },
   Else {
-    body: Vec<Node>,
+    body: Vec<Spanned<Node>>,
   },
   For {
-    decl: Var,
-    expr: Box<Node>,
-    body: Vec<Node>,
+    decl: Spanned<Var>,
+    expr: Box<Spanned<Node>>,
+    body: Vec<Spanned<Node>>,
   },
   While {
-    expr: Box<Node>,
-    body: Vec<Node>,
+    expr: Box<Spanned<Node>>,
+    body: Vec<Spanned<Node>>,
   },
   Loop {
-    body: Vec<Node>,
+    body: Vec<Spanned<Node>>,
+  },
+  Return(Option<Box<Spanned<Node>>>),
+  // `import a.b.c` or `import a.b.c -> name`
+  Import {
+    path: Vec<String>,
+    alias: Option<String>,
+  },
+  // `save name` or `save name = expr`; only the second can have a `doc`
+  Save {
+    name: String,
+    rhs: Option<Box<Spanned<Node>>>,
+    doc: Option<String>,
   },
-  Return(Option<Box<Node>>),
   Break,
   Continue,
   Expr,
   Pass,
+  // placeholder for a statement that failed to parse
+  Error,
   Index {
-    lhs: Box<Node>,
-    rhs: Box<Node>,
+    lhs: Box<Spanned<Node>>,
+    rhs: Box<Spanned<Node>>,
   },
 
   Method {
-    owner: Box<Node>,
-    method: Box<Node>,
-    args: Vec<Node>,
+    owner: Box<Spanned<Node>>,
+    method: Box<Spanned<Node>>,
+    args: Vec<Spanned<Node>>,
   },
 
   Func {
     params: Vec<String>,
-    body: Vec<Node>,
+    body: Vec<Spanned<Node>>,
+    doc: Option<String>,
   },
 
   Lambda {
     params: Vec<String>,
-    expr: Box<Node>,
+    expr: Box<Spanned<Node>>,
   },
 
   Call {
-    func: Box<Node>,
-    args: Vec<Node>,
+    func: Box<Spanned<Node>>,
+    args: Vec<Spanned<Node>>,
   },
 
+  Array(Vec<Spanned<Node>>),
+  Dict(Vec<(Spanned<Node>, Spanned<Node>)>),
+
   BinExpr {
-    lhs: Box<Node>,
+    lhs: Box<Spanned<Node>>,
     op: Token,
-    rhs: Box<Node>,
+    rhs: Box<Spanned<Node>>,
   },
 
   UnExpr {
-    val: Box<Node>,
+    val: Box<Spanned<Node>>,
     op: Token,
   },
 
+  // `and` / `or`, kept apart from BinExpr because they short-circuit
+  LogicExpr {
+    lhs: Box<Spanned<Node>>,
+    op: Token,
+    rhs: Box<Spanned<Node>>,
+  },
+
   // Literals
   Null,
   Bool(bool),
   Float(f64),
   Int(i64),
   Str(String),
+  // 'a ${b} c' is [Str("a "), b, Str(" c")]
+  Interp(Vec<Spanned<Node>>),
   Name(String),
   Table,
 }
@@ -117,7 +197,98 @@ pub enum ParseErrorKind {
   UnexpectedEOF,
   UnknownBinaryOperator,
   UnknownUnaryOperator,
-  UnusedPlaces,
+  MissingIf,
+}
+
+// Something the parser would have accepted in place of an unexpected token
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expected {
+  Token(Token),
+  Rule(&'static str),
+}
+
+impl fmt::Display for Expected {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Expected::Token(ref tok) => write!(f, "{}", tok),
+      Expected::Rule(rule) => write!(f, "{}", rule),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+  pub kind: ParseErrorKind,
+  // `None` only if the token stream ran out without an EOF token
+  pub span: Option<Span>,
+  pub expected: Vec<Expected>,
+  pub message: String,
+}
+
+impl ParseError {
+  fn new(kind: ParseErrorKind, span: Option<Span>, expected: Vec<Expected>) -> ParseError {
+    let message = describe(&kind, &expected, None);
+    ParseError {
+      kind,
+      span,
+      expected,
+      message,
+    }
+  }
+
+  // Explain where the expected tokens were supposed to go, eg "after for-loop
+  // declaration"
+  fn after(mut self, what: &str) -> ParseError {
+    self.message = describe(&self.kind, &self.expected, Some(what));
+    self
+  }
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.message)
+  }
+}
+
+fn describe(kind: &ParseErrorKind, expected: &[Expected], after: Option<&str>) -> String {
+  let found = match *kind {
+    UnexpectedToken(ref tok) => tok.to_string(),
+    UnexpectedEOF => String::from("end of input"),
+    UnknownBinaryOperator => return String::from("unknown binary operator"),
+    UnknownUnaryOperator => return String::from("unknown unary operator"),
+    MissingIf => return String::from("`else` without a matching `if`"),
+  };
+
+  let wanted = match expected.len() {
+    0 => String::from("something else"),
+    1 => expected[0].to_string(),
+    _ => {
+      let names: Vec<String> = expected.iter().map(|x| x.to_string()).collect();
+      format!("one of {}", names.join(", "))
+    }
+  };
+
+  match after {
+    Some(what) => format!("expected {} after {}, found {}", wanted, what, found),
+    None => format!("expected {}, found {}", wanted, found),
+  }
+}
+
+// Build an error for the next token in `it`, which wasn't any of `expected`
+fn unexpected(it: &mut ParseIter, expected: Vec<Expected>) -> ParseError {
+  match it.peek() {
+    Some(&tok) => ParseError::new(UnexpectedToken(tok.node.clone()), Some(tok.span), expected),
+    None => ParseError::new(UnexpectedEOF, None, expected),
+  }
+}
+
+fn expected_expr() -> Vec<Expected> {
+  vec![Expected::Rule("expression")]
+}
+
+// also used for dict keys, which look the same
+fn expected_decl() -> Vec<Expected> {
+  vec![Expected::Token(Token::Sql), Expected::Rule("name")]
 }
 
 // Return true if the next token in `it` is `kind`
@@ -142,23 +313,37 @@ fn use_token(it: &mut ParseIter, kind: Token) -> bool {
 }
 
 // Panic if the next token in `it` is *not* `kind`
-fn require_token(it: &mut ParseIter, kind: Token) -> Result<(), ParseErrorKind> {
+// On success, return the span of the consumed token
+fn require_token(it: &mut ParseIter, kind: Token) -> Result<Span, ParseError> {
   if let Some(&tok) = it.peek() {
     if tok.node == kind {
       it.next();
-      return Ok(());
+      return Ok(tok.span);
     }
-
-    return Err(UnexpectedToken(tok.node.clone()));
   }
 
-  return Err(UnexpectedEOF);
+  Err(unexpected(it, vec![Expected::Token(kind)]))
+}
+
+// Return the span from `start` through the end of the last node in `body`
+fn span_to(start: Span, body: &[Spanned<Node>]) -> Span {
+  match body.last() {
+    Some(last) => start.merge(last.span),
+    None => start,
+  }
 }
 
 fn op_precedence(op: &Token) -> Op {
   match *op {
+    Token::LogOr => Op::Left(1),
+    Token::LogAnd => Op::Left(2),
+    Token::Eql | Token::Ne | Token::Lt | Token::Le | Token::Gt | Token::Ge => Op::Left(5),
+    Token::Or => Op::Left(6),
+    Token::Neg => Op::Left(7),
+    Token::And => Op::Left(8),
+    Token::Shl | Token::Shr => Op::Left(9),
     Token::Add | Token::Sub => Op::Left(10),
-    Token::Div | Token::Mul => Op::Left(20),
+    Token::Div | Token::Mul | Token::Pct | Token::Fdiv => Op::Left(20),
     Token::Car => Op::Right(30),
     _ => Op::None,
   }
@@ -171,23 +356,30 @@ fn parse_ml_expr(it: &mut ParseIter) -> Parse {
         it.next();
         require_token(it, Token::Pal)?;
         let params = parse_fn_params(it)?;
-        require_token(it, Token::Par)?;
+        let end = require_token(it, Token::Par).map_err(|e| e.after("function parameters"))?;
         let body = parse_block(it)?;
-        Ok(Node::Func {
-          params: params,
-          body: body,
+        Ok(Spanned {
+          span: span_to(tok.span.merge(end), &body),
+          node: Node::Func {
+            params,
+            body,
+            doc: None,
+          },
         })
       }
       Token::Catch => {
         it.next();
         let block = parse_block(it)?;
-        Ok(Node::Catch(block))
+        Ok(Spanned {
+          span: span_to(tok.span, &block),
+          node: Node::Catch(block),
+        })
       }
       _ => parse_il_expr(it),
     };
   }
 
-  Err(UnexpectedEOF)
+  Err(unexpected(it, expected_expr()))
 }
 
 fn parse_il_expr(it: &mut ParseIter) -> Parse {
@@ -196,117 +388,108 @@ fn parse_il_expr(it: &mut ParseIter) -> Parse {
       Token::Or => {
         it.next();
         let params = parse_fn_params(it)?;
-        require_token(it, Token::Or)?;
+        require_token(it, Token::Or).map_err(|e| e.after("lambda parameters"))?;
         let expr = parse_il_expr(it)?;
-        Ok(Node::Lambda {
-          params: params,
-          expr: Box::new(expr),
+        Ok(Spanned {
+          span: tok.span.merge(expr.span),
+          node: Node::Lambda {
+            params,
+            expr: Box::new(expr),
+          },
         })
       }
       _ => parse_bin_expr(it),
     };
   }
 
-  Err(UnexpectedEOF)
+  Err(unexpected(it, expected_expr()))
 }
 
-fn parse_bin_expr(it: &mut ParseIter) -> Parse {
-  let mut expr = parse_un_expr(it)?;
+// Build the node for `lhs op rhs`
+fn bin_node(lhs: Spanned<Node>, op: Token, rhs: Spanned<Node>) -> Spanned<Node> {
+  let span = lhs.span.merge(rhs.span);
+  let lhs = Box::new(lhs);
+  let rhs = Box::new(rhs);
 
-  // prevents this from breaking the LHS until we know we made it
-  // otherwise, things like (2 + 3) * 4 get restructured into 2 + (3 * 4)
-  let mut break_left = false;
+  let node = match op {
+    Token::LogAnd | Token::LogOr => Node::LogicExpr { lhs, op, rhs },
+    _ => Node::BinExpr { lhs, op, rhs },
+  };
 
-  while let Some(&tok) = it.peek() {
-    let prec = op_precedence(&tok.node);
+  Spanned { node, span }
+}
 
-    if let Op::None = prec {
-      break;
-    }
+// Binding powers of prefix and postfix operations. Both bind tighter than any
+// binary operator, and postfix tighter than prefix, so `-a.b ^ c` is
+// `(-(a.b)) ^ c`
+const PREFIX_POWER: u32 = 100;
+const POSTFIX_POWER: u32 = 101;
+
+// Left and right binding powers of a binary operator, derived from its
+// precedence. The weaker side decides associativity: in `a - b - c` the first
+// `-` holds `b` tighter than the second can take it
+pub fn binding_power(op: &Token) -> Option<(u32, u32)> {
+  match op_precedence(op) {
+    Op::Left(n) => Some((n * 2, n * 2 + 1)),
+    Op::Right(n) => Some((n * 2 + 1, n * 2)),
+    Op::None => None,
+  }
+}
 
-    it.next();
+fn parse_bin_expr(it: &mut ParseIter) -> Parse {
+  parse_expr_bp(it, 0)
+}
 
-    let rhs = parse_un_expr(it)?;
+// Parse an expression whose operators all bind at least as tightly as
+// `min_bp`, leaving any weaker operator for the caller
+fn parse_expr_bp(it: &mut ParseIter, min_bp: u32) -> Parse {
+  let mut expr = parse_prefix(it)?;
 
-    expr = match (break_left, expr.clone()) {
-      (
-        true,
-        Node::BinExpr {
-          lhs: cur_lhs,
-          op: cur_op,
-          rhs: cur_rhs,
-        },
-      ) => {
-        let cur_prec = op_precedence(&cur_op);
-        match (cur_prec, prec) {
-          // these should never happen
-          (_, Op::None) => break,
-          (Op::None, _) => break,
-
-          // left-to-right
-          // there has to be a better way to handle this, no?
-          (Op::Left(n), Op::Left(m)) if n >= m => Node::BinExpr {
-            lhs: Box::new(expr),
-            op: tok.node.clone(),
-            rhs: Box::new(rhs),
-          },
-          (Op::Right(n), Op::Right(m)) if n > m => Node::BinExpr {
-            lhs: Box::new(expr),
-            op: tok.node.clone(),
-            rhs: Box::new(rhs),
-          },
-          (Op::Right(n), Op::Left(m)) if n >= m => Node::BinExpr {
-            lhs: Box::new(expr),
-            op: tok.node.clone(),
-            rhs: Box::new(rhs),
-          },
-          (Op::Left(n), Op::Right(m)) if n >= m => Node::BinExpr {
-            lhs: Box::new(expr),
-            op: tok.node.clone(),
-            rhs: Box::new(rhs),
-          },
-
-          // right-to-left
-          _ => Node::BinExpr {
-            lhs: cur_lhs,
-            op: cur_op,
-            rhs: Box::new(Node::BinExpr {
-              lhs: cur_rhs,
-              op: tok.node.clone(),
-              rhs: Box::new(rhs),
-            }),
-          },
-        }
+  while let Some(&tok) = it.peek() {
+    if is_postfix(&tok.node) {
+      if POSTFIX_POWER < min_bp {
+        break;
       }
-      _ => Node::BinExpr {
-        lhs: Box::new(expr),
-        op: tok.node.clone(),
-        rhs: Box::new(rhs),
-      },
+      expr = parse_postfix(it, expr)?;
+      continue;
+    }
+
+    let (left_bp, right_bp) = match binding_power(&tok.node) {
+      Some(bp) => bp,
+      None => break,
     };
 
-    break_left = true;
+    if left_bp < min_bp {
+      break;
+    }
+
+    it.next();
+    let rhs = parse_expr_bp(it, right_bp)?;
+    expr = bin_node(expr, tok.node.clone(), rhs);
   }
 
   Ok(expr)
 }
 
-fn parse_un_expr(it: &mut ParseIter) -> Parse {
+fn parse_prefix(it: &mut ParseIter) -> Parse {
   if let Some(&tok) = it.peek() {
     return match tok.node {
       Token::Sub | Token::Not | Token::Neg => {
         it.next();
-        let val = parse_un_expr(it)?;
-        Ok(Node::UnExpr {
-          op: tok.node.clone(),
-          val: Box::new(val),
+        let val = parse_expr_bp(it, PREFIX_POWER)?;
+        Ok(Spanned {
+          span: tok.span.merge(val.span),
+          node: Node::UnExpr {
+            op: tok.node.clone(),
+            val: Box::new(val),
+          },
         })
       }
-      _ => parse_simple(it),
+      _ => parse_atom(it),
     };
   }
 
-  Err(UnexpectedEOF)
+  Err(unexpected(it, expected_expr()))
 }
 
 /* unused, here for reference
@@ -333,7 +516,26 @@ fn parse_index(it: &mut ParseIter) -> Parse {
 }
 */
 
-fn parse_fn_params(it: &mut ParseIter) -> Result<Vec<String>, ParseErrorKind> {
+// Either a bare name, which is used as a string like in `foo.bar`, or a
+// computed key in square brackets
+fn parse_dict_key(it: &mut ParseIter) -> Parse {
+  if let Some(&tok) = it.peek() {
+    return match tok.node {
+      Token::Sql => {
+        it.next();
+        let key = parse_bin_expr(it)?;
+        require_token(it, Token::Sqr).map_err(|e| e.after("dict key"))?;
+        Ok(key)
+      }
+      Token::Name(_) => parse_name_as_str(it),
+      _ => Err(unexpected(it, expected_decl())),
+    };
+  }
+
+  Err(unexpected(it, expected_decl()))
+}
+
+fn parse_fn_params(it: &mut ParseIter) -> Result<Vec<String>, ParseError> {
   let mut params: Vec<String> = Vec::new();
   while let Some(&tok) = it.peek() {
     match tok.node {
@@ -351,9 +553,9 @@ fn parse_fn_params(it: &mut ParseIter) -> Result<Vec<String>, ParseErrorKind> {
   Ok(params)
 }
 
-fn parse_fn_args(it: &mut ParseIter) -> Result<Vec<Node>, ParseErrorKind> {
+fn parse_fn_args(it: &mut ParseIter) -> Result<Spanned<Vec<Spanned<Node>>>, ParseError> {
   let mut args = Vec::new();
-  require_token(it, Token::Pal)?;
+  let start = require_token(it, Token::Pal)?;
   while !peek_token(it, Token::Par) {
     let arg = parse_il_expr(it)?;
     args.push(arg);
@@ -361,57 +563,77 @@ fn parse_fn_args(it: &mut ParseIter) -> Result<Vec<Node>, ParseErrorKind> {
       break;
     }
   }
-  require_token(it, Token::Par)?;
-  Ok(args)
+  let end = require_token(it, Token::Par)?;
+  Ok(Spanned {
+    node: args,
+    span: start.merge(end),
+  })
 }
 
-fn parse_simple(it: &mut ParseIter) -> Parse {
-  let mut atom = parse_atom(it)?;
-  while let Some(&tok) = it.peek() {
-    match tok.node {
-      Token::Col => {
-        it.next();
-        let method = parse_name_as_str(it)?;
-        let args = parse_fn_args(it)?;
-        atom = Node::Method {
-          owner: Box::new(atom),
+fn is_postfix(tok: &Token) -> bool {
+  matches!(*tok, Token::Col | Token::Pal | Token::Sql | Token::Dot)
+}
+
+// Apply the call, method call or index that starts at the next token
+fn parse_postfix(it: &mut ParseIter, lhs: Spanned<Node>) -> Parse {
+  let tok = match it.peek() {
+    Some(&tok) => tok,
+    None => return Err(unexpected(it, expected_expr())),
+  };
+
+  match tok.node {
+    Token::Col => {
+      it.next();
+      let method = parse_name_as_str(it)?;
+      let args = parse_fn_args(it)?;
+      Ok(Spanned {
+        span: lhs.span.merge(args.span),
+        node: Node::Method {
+          owner: Box::new(lhs),
           method: Box::new(method),
-          args: args,
-        };
-      }
+          args: args.node,
+        },
+      })
+    }
 
-      Token::Pal => {
-        let args = parse_fn_args(it)?;
-        atom = Node::Call {
-          func: Box::new(atom),
-          args: args,
-        };
-      }
+    Token::Pal => {
+      let args = parse_fn_args(it)?;
+      Ok(Spanned {
+        span: lhs.span.merge(args.span),
+        node: Node::Call {
+          func: Box::new(lhs),
+          args: args.node,
+        },
+      })
+    }
 
-      Token::Sql => {
-        it.next();
-        let idx = parse_bin_expr(it)?;
-        require_token(it, Token::Sqr)?;
-        atom = Node::Index {
-          lhs: Box::new(atom),
+    Token::Sql => {
+      it.next();
+      let idx = parse_bin_expr(it)?;
+      let end = require_token(it, Token::Sqr).map_err(|e| e.after("index"))?;
+      Ok(Spanned {
+        span: lhs.span.merge(end),
+        node: Node::Index {
+          lhs: Box::new(lhs),
           rhs: Box::new(idx),
-        };
-      }
+        },
+      })
+    }
 
-      Token::Dot => {
-        it.next();
-        let idx = parse_name_as_str(it)?;
-        atom = Node::Index {
-          lhs: Box::new(atom),
+    Token::Dot => {
+      it.next();
+      let idx = parse_name_as_str(it)?;
+      Ok(Spanned {
+        span: lhs.span.merge(idx.span),
+        node: Node::Index {
+          lhs: Box::new(lhs),
           rhs: Box::new(idx),
-        };
-      }
-
-      _ => break,
+        },
+      })
     }
-  }
 
-  Ok(atom)
+    _ => Err(unexpected(it, expected_expr())),
+  }
 }
 
 fn parse_atom(it: &mut ParseIter) -> Parse {
@@ -420,88 +642,121 @@ fn parse_atom(it: &mut ParseIter) -> Parse {
       Token::Pal => {
         it.next();
         let out = parse_bin_expr(it)?;
-        require_token(it, Token::Par)?;
-        Ok(out)
+        let end = require_token(it, Token::Par)?;
+        Ok(Spanned {
+          node: out.node,
+          span: tok.span.merge(end),
+        })
+      }
+      Token::Sql => {
+        it.next();
+        let mut items = Vec::new();
+        while !peek_token(it, Token::Sqr) {
+          let item = parse_il_expr(it)?;
+          items.push(item);
+          if !use_token(it, Token::Com) {
+            break;
+          }
+        }
+        let end = require_token(it, Token::Sqr)?;
+        Ok(Spanned {
+          node: Node::Array(items),
+          span: tok.span.merge(end),
+        })
+      }
+      Token::Cul => {
+        it.next();
+        let mut items = Vec::new();
+        while !peek_token(it, Token::Cur) {
+          let key = parse_dict_key(it)?;
+          require_token(it, Token::Ass).map_err(|e| e.after("dict key"))?;
+          let val = parse_il_expr(it)?;
+          items.push((key, val));
+          if !use_token(it, Token::Com) {
+            break;
+          }
+        }
+        let end = require_token(it, Token::Cur)?;
+        Ok(Spanned {
+          node: Node::Dict(items),
+          span: tok.span.merge(end),
+        })
       }
       _ => parse_quark(it),
     };
   }
 
-  Err(UnexpectedEOF)
+  Err(unexpected(it, expected_expr()))
 }
 
-fn parse_name_as_str(it: &mut ParseIter) -> Parse {
+fn parse_ident(it: &mut ParseIter) -> Result<Spanned<String>, ParseError> {
   if let Some(&tok) = it.peek() {
-    return match tok.node {
-      Token::Name(ref x) => {
-        it.next();
-        Ok(Node::Str(x.clone()))
-      }
-      ref x => Err(UnexpectedToken(x.clone())),
-    };
+    if let Token::Name(ref x) = tok.node {
+      it.next();
+      return Ok(Spanned {
+        node: x.clone(),
+        span: tok.span,
+      });
+    }
   }
 
-  Err(UnexpectedEOF)
+  Err(unexpected(it, vec![Expected::Rule("name")]))
 }
 
-fn parse_name(it: &mut ParseIter) -> Parse {
-  if let Some(&tok) = it.peek() {
-    return match tok.node {
-      Token::Name(ref x) => {
-        it.next();
-        Ok(Node::Name(x.clone()))
-      }
-      ref x => Err(UnexpectedToken(x.clone())),
-    };
-  }
-
-  Err(UnexpectedEOF)
+fn parse_name_as_str(it: &mut ParseIter) -> Parse {
+  parse_ident(it).map(|name| name.map_node(Node::Str))
 }
 
 fn parse_quark(it: &mut ParseIter) -> Parse {
   if let Some(&tok) = it.peek() {
-    return match tok.node {
-      Token::Null => {
-        it.next();
-        Ok(Node::Null)
-      }
-      Token::Bool(x) => {
-        it.next();
-        Ok(Node::Bool(x))
-      }
-      Token::Float(x) => {
-        it.next();
-        Ok(Node::Float(x))
-      }
-      Token::Int(x) => {
-        it.next();
-        Ok(Node::Int(x))
-      }
-      Token::Str(ref x) => {
-        it.next();
-        Ok(Node::Str(x.clone()))
-      }
-      Token::Name(ref x) => {
-        it.next();
-        Ok(Node::Name(x.clone()))
-      }
-      Token::Table => {
-        it.next();
-        Ok(Node::Table)
-      }
-      ref x => Err(UnexpectedToken(x.clone())),
+    let node = match tok.node {
+      Token::Null => Node::Null,
+      Token::Bool(x) => Node::Bool(x),
+      Token::Float(x) => Node::Float(x),
+      Token::Int(x) => Node::Int(x),
+      Token::Str(ref x) => Node::Str(x.clone()),
+      Token::Interp(ref parts) => Node::Interp(parse_interp(parts)?),
+      Token::Name(ref x) => Node::Name(x.clone()),
+      Token::Table => Node::Table,
+      _ => return Err(unexpected(it, expected_expr())),
     };
+
+    it.next();
+    return Ok(Spanned {
+      node,
+      span: tok.span,
+    });
   }
 
-  Err(UnexpectedEOF)
+  Err(unexpected(it, expected_expr()))
 }
 
-fn parse_decl(it: &mut ParseIter) -> Result<Var, ParseErrorKind> {
+// Each literal chunk of an interpolated string becomes a Str, and each `${...}`
+// the expression inside it
+fn parse_interp(parts: &[Spanned<Fragment>]) -> Result<Vec<Spanned<Node>>, ParseError> {
+  parts
+    .iter()
+    .map(|part| match part.node {
+      Fragment::Str(ref x) => Ok(Spanned {
+        node: Node::Str(x.clone()),
+        span: part.span,
+      }),
+      Fragment::Expr(ref tokens) => {
+        let mut it = ParseIter::new(tokens);
+        let expr = parse_il_expr(&mut it)?;
+        require_token(&mut it, Token::Cur).map_err(|e| e.after("interpolated expression"))?;
+        Ok(expr)
+      }
+    })
+    .collect()
+}
+
+fn parse_decl(it: &mut ParseIter) -> Result<Spanned<Var>, ParseError> {
   if let Some(&tok) = it.peek() {
     return match tok.node {
       Token::Sql => {
         it.next();
-        let mut pieces: Vec<Var> = Vec::new();
+        let mut pieces: Vec<Spanned<Var>> = Vec::new();
         loop {
           let new_piece = parse_decl(it)?;
           pieces.push(new_piece);
@@ -509,69 +764,117 @@ fn parse_decl(it: &mut ParseIter) -> Result<Var, ParseErrorKind> {
             break;
           }
         }
-        require_token(it, Token::Sqr)?;
-        Ok(Var::Multi(pieces))
+        let end = require_token(it, Token::Sqr)?;
+        Ok(Spanned {
+          node: Var::Multi(pieces),
+          span: tok.span.merge(end),
+        })
       }
       Token::Name(ref x) => {
         it.next();
-        Ok(Var::Single(x.clone()))
+        Ok(Spanned {
+          node: Var::Single(x.clone()),
+          span: tok.span,
+        })
       }
-      ref x => Err(UnexpectedToken(x.clone())),
+      _ => Err(unexpected(it, expected_decl())),
     };
   }
 
-  Err(UnexpectedEOF)
+  Err(unexpected(it, expected_decl()))
 }
 
-fn parse_place(it: &mut ParseIter) -> Result<Place, ParseErrorKind> {
-  if let Some(&tok) = it.peek() {
-    return match tok.node {
-      Token::Sql => {
-        it.next();
-        let mut pieces: Vec<Place> = Vec::new();
-        loop {
-          let new_piece = parse_place(it)?;
-          pieces.push(new_piece);
-          if !use_token(it, Token::Com) {
-            break;
-          }
-        }
-        require_token(it, Token::Sqr)?;
-        Ok(Place::Multi(pieces))
-      }
-
-      _ => {
-        let node = parse_il_expr(it)?;
-        Ok(Place::Single(Box::new(node)))
-      }
-    };
+// Reinterpret an expression as the target of an assignment
+// array literals become destructuring patterns, everything else is left for
+// the semantic checker to validate
+fn into_place(node: Spanned<Node>) -> Spanned<Place> {
+  let span = node.span;
+  match node.node {
+    Node::Array(items) => Spanned {
+      node: Place::Multi(items.into_iter().map(into_place).collect()),
+      span,
+    },
+    _ => Spanned {
+      node: Place::Single(Box::new(node)),
+      span,
+    },
   }
-
-  Err(UnexpectedEOF)
 }
 
 fn parse_assn(it: &mut ParseIter) -> Parse {
-  let place = parse_place(it)?;
+  // we can't tell `[a, b] = ...` from an array literal until we see the `=`,
+  // so parse an expression and convert it to a place if it's assigned to
+  let expr = parse_il_expr(it)?;
 
   if let Some(&tok) = it.peek() {
     return match tok.node {
       Token::Ass => {
         it.next();
         let rhs = parse_ml_expr(it)?;
-        Ok(Node::Assn {
-          lhs: place,
-          rhs: Box::new(rhs),
+        Ok(Spanned {
+          span: expr.span.merge(rhs.span),
+          node: Node::Assn {
+            lhs: into_place(expr),
+            rhs: Box::new(rhs),
+            doc: None,
+          },
         })
       }
 
-      _ => match place {
-        Place::Single(bx) => Ok(Node::Stmt(bx)),
-        Place::Multi(_) => Err(UnusedPlaces),
-      },
+      _ => Ok(Spanned {
+        span: expr.span,
+        node: Node::Stmt(Box::new(expr)),
+      }),
     };
   }
 
-  Err(UnexpectedEOF)
+  Err(unexpected(it, vec![Expected::Token(Token::Ass)]))
+}
+
+// Parse the rest of an `if` or `else if` whose keywords started at `start`,
+// including any `else` chain that follows it
+fn parse_if(it: &mut ParseIter, start: Span) -> Parse {
+  let cond = parse_bin_expr(it)?;
+  let body = parse_block(it)?;
+  let els = parse_else(it)?;
+
+  let span = match els {
+    Some(ref els) => start.merge(els.span),
+    None => span_to(start.merge(cond.span), &body),
+  };
+
+  Ok(Spanned {
+    node: Node::If {
+      cond: Box::new(cond),
+      body,
+      els,
+    },
+    span,
+  })
+}
+
+fn parse_else(it: &mut ParseIter) -> Result<Option<Box<Spanned<Node>>>, ParseError> {
+  // the block is followed by an End like any other statement, so an `else`
+  // that belongs to this `if` is on the other side of it
+  let has_else =
+    peek_token(it, Token::End) && it.peek_second().is_some_and(|tok| tok.node == Token::Else);
+
+  if !has_else {
+    return Ok(None);
+  }
+
+  it.next();
+  let start = require_token(it, Token::Else)?;
+
+  if use_token(it, Token::If) {
+    return parse_if(it, start).map(|node| Some(Box::new(node)));
+  }
+
+  let body = parse_block(it)?;
+  Ok(Some(Box::new(Spanned {
+    span: span_to(start, &body),
+    node: Node::Else { body },
+  })))
 }
 
 fn parse_stmt(it: &mut ParseIter) -> Parse {
@@ -579,50 +882,55 @@ fn parse_stmt(it: &mut ParseIter) -> Parse {
     return match tok.node {
       Token::Break => {
         it.next();
-        Ok(Node::Break)
+        Ok(Spanned {
+          node: Node::Break,
+          span: tok.span,
+        })
       }
 
       Token::Continue => {
         it.next();
-        Ok(Node::Continue)
+        Ok(Spanned {
+          node: Node::Continue,
+          span: tok.span,
+        })
       }
 
       Token::If => {
         it.next();
-        let cond = parse_bin_expr(it)?;
-        let body = parse_block(it)?;
-        Ok(Node::If {
-          cond: Box::new(cond),
-          body: body,
-          els: None,
-        })
+        parse_if(it, tok.span)
       }
 
-      Token::Else => {
+      Token::Else => Err(ParseError::new(MissingIf, Some(tok.span), vec![])),
+
+      Token::Var => {
         it.next();
-        if use_token(it, Token::If) {
-          let cond = parse_bin_expr(it)?;
-          let body = parse_block(it)?;
-          Ok(Node::ElseIf {
-            cond: Box::new(cond),
-            body: body,
-          })
-        } else {
-          let body = parse_block(it)?;
-          Ok(Node::Else { body: body })
-        }
+        let decl = parse_decl(it)?;
+        require_token(it, Token::Ass).map_err(|e| e.after("variable declaration"))?;
+        let rhs = parse_ml_expr(it)?;
+        Ok(Spanned {
+          span: tok.span.merge(rhs.span),
+          node: Node::Decl {
+            decl,
+            rhs: Box::new(rhs),
+            doc: None,
+          },
+        })
       }
 
       Token::For => {
         it.next();
         let decl = parse_decl(it)?;
-        require_token(it, Token::In)?;
+        require_token(it, Token::In).map_err(|e| e.after("for-loop declaration"))?;
         let expr = parse_il_expr(it)?;
         let body = parse_block(it)?;
-        Ok(Node::For {
-          decl: decl,
-          expr: Box::new(expr),
-          body: body,
+        Ok(Spanned {
+          span: span_to(tok.span.merge(expr.span), &body),
+          node: Node::For {
+            decl,
+            expr: Box::new(expr),
+            body,
+          },
         })
       }
 
@@ -630,72 +938,283 @@ fn parse_stmt(it: &mut ParseIter) -> Parse {
         it.next();
         let expr = parse_il_expr(it)?;
         let body = parse_block(it)?;
-        Ok(Node::While {
-          expr: Box::new(expr),
-          body: body,
+        Ok(Spanned {
+          span: span_to(tok.span.merge(expr.span), &body),
+          node: Node::While {
+            expr: Box::new(expr),
+            body,
+          },
         })
       }
 
       Token::Loop => {
         it.next();
         let body = parse_block(it)?;
-        Ok(Node::Loop { body: body })
+        Ok(Spanned {
+          span: span_to(tok.span, &body),
+          node: Node::Loop { body },
+        })
       }
 
       Token::Return => {
         it.next();
-        let val = if peek_token(it, Token::End) {
+        // a bare `return` is followed by whatever ends the statement
+        let bare = it.peek().is_none_or(|tok| {
+          matches!(
+            tok.node,
+            Token::End | Token::Semi | Token::Exit | Token::EOF
+          )
+        });
+        let val = if bare {
           None
         } else {
           let val = parse_ml_expr(it)?;
           Some(Box::new(val))
         };
-        Ok(Node::Return(val))
+        Ok(Spanned {
+          span: match val {
+            Some(ref val) => tok.span.merge(val.span),
+            None => tok.span,
+          },
+          node: Node::Return(val),
+        })
       }
 
       Token::Pass => {
         it.next();
-        Ok(Node::Pass)
+        Ok(Spanned {
+          node: Node::Pass,
+          span: tok.span,
+        })
+      }
+
+      Token::Import => {
+        it.next();
+        let mut name = parse_ident(it)?;
+        let mut span = tok.span.merge(name.span);
+        let mut path = vec![name.node];
+
+        while use_token(it, Token::Dot) {
+          name = parse_ident(it)?;
+          span = span.merge(name.span);
+          path.push(name.node);
+        }
+
+        let alias = if use_token(it, Token::Arr) {
+          let alias = parse_ident(it)?;
+          span = span.merge(alias.span);
+          Some(alias.node)
+        } else {
+          None
+        };
+
+        Ok(Spanned {
+          node: Node::Import { path, alias },
+          span,
+        })
+      }
+
+      Token::Save => {
+        it.next();
+        let name = parse_ident(it)?;
+        let rhs = if use_token(it, Token::Ass) {
+          Some(Box::new(parse_ml_expr(it)?))
+        } else {
+          None
+        };
+
+        Ok(Spanned {
+          span: match rhs {
+            Some(ref rhs) => tok.span.merge(rhs.span),
+            None => tok.span.merge(name.span),
+          },
+          node: Node::Save {
+            name: name.node,
+            rhs,
+            doc: None,
+          },
+        })
       }
 
-      Token::Func | Token::Catch => parse_ml_expr(it).map(|expr| Node::Stmt(Box::new(expr))),
+      Token::Func | Token::Catch => parse_ml_expr(it).map(|expr| Spanned {
+        span: expr.span,
+        node: Node::Stmt(Box::new(expr)),
+      }),
 
       _ => parse_assn(it),
     };
   }
 
-  Err(UnexpectedEOF)
+  Err(unexpected(it, vec![Expected::Rule("statement")]))
 }
 
-fn parse_block(it: &mut ParseIter) -> Result<Vec<Node>, ParseErrorKind> {
-  let mut nodes: Vec<Node> = vec![];
+// Record `err` and skip the rest of the broken statement, stopping before the
+// next End or Exit that isn't part of a nested block
+fn recover(it: &mut ParseIter, err: ParseError, start: Span) -> Spanned<Node> {
+  // cover whatever the failed parse already got through, too
+  let mut span = match it.last {
+    Some(last) if last.low() > start.low() => start.merge(last),
+    _ => start,
+  };
+  let mut depth = 0;
 
-  require_token(it, Token::Enter)?;
+  it.errors.push(err);
 
-  while !peek_token(it, Token::Exit) {
-    let stmt = parse_stmt(it)?;
-    nodes.push(stmt);
-    require_token(it, Token::End)?;
+  while let Some(&tok) = it.peek() {
+    match tok.node {
+      Token::End | Token::Semi | Token::Exit if depth == 0 => break,
+      Token::EOF => break,
+      Token::Enter => depth += 1,
+      Token::Exit => depth -= 1,
+      Token::End => {}
+
+      // only real tokens count towards the span, not line breaks
+      _ => span = span.merge(tok.span),
+    }
+
+    it.next();
+  }
+
+  Spanned {
+    node: Node::Error,
+    span,
   }
+}
+
+// Parse statements up to (but not including) `until` or EOF, recovering from
+// any errors along the way
+fn parse_stmts(it: &mut ParseIter, until: Token) -> Vec<Spanned<Node>> {
+  let mut nodes: Vec<Spanned<Node>> = vec![];
+  let mut doc: Option<String> = None;
+
+  while let Some(&tok) = it.peek() {
+    if tok.node == until || tok.node == Token::EOF {
+      break;
+    }
+
+    // consecutive `##` lines make up one doc comment
+    if let Token::Doc(ref line) = tok.node {
+      it.next();
+      use_token(it, Token::End);
+      doc = Some(match doc {
+        Some(doc) => format!("{}\n{}", doc, line),
+        None => line.clone(),
+      });
+      continue;
+    }
 
+    let consumed = it.consumed;
+    let stmt = parse_stmt(it).and_then(|stmt| {
+      end_stmt(it)?;
+      Ok(stmt)
+    });
+
+    match stmt {
+      Ok(mut stmt) => {
+        if let Some(doc) = doc.take() {
+          attach_doc(&mut stmt, doc);
+        }
+        nodes.push(stmt);
+      }
+      Err(err) => {
+        doc = None;
+        nodes.push(recover(it, err, tok.span));
+
+        // always make progress, even on a stray Exit
+        let semi = use_token(it, Token::Semi);
+        if !use_token(it, Token::End) && !semi && it.consumed == consumed {
+          it.next();
+        }
+      }
+    }
+  }
+
+  nodes
+}
+
+// Give a statement its doc comment, if it's the kind of statement that can
+// have one. Otherwise there's nothing to document, so it's just a comment
+fn attach_doc(stmt: &mut Spanned<Node>, text: String) {
+  let slot = match stmt.node {
+    Node::Assn { ref mut doc, .. } | Node::Decl { ref mut doc, .. } => doc,
+    Node::Save {
+      rhs: Some(_),
+      ref mut doc,
+      ..
+    } => doc,
+    Node::Stmt(ref mut expr) => match expr.node {
+      Node::Func { ref mut doc, .. } => doc,
+      _ => return,
+    },
+    _ => return,
+  };
+
+  *slot = Some(text);
+}
+
+// A statement ends at a line break or a `;`, which may also end the line
+fn end_stmt(it: &mut ParseIter) -> Result<(), ParseError> {
+  if use_token(it, Token::Semi) {
+    use_token(it, Token::End);
+    return Ok(());
+  }
+
+  if use_token(it, Token::End) {
+    return Ok(());
+  }
+
+  Err(unexpected(
+    it,
+    vec![Expected::Token(Token::Semi), Expected::Token(Token::End)],
+  ))
+}
+
+fn parse_block(it: &mut ParseIter) -> Result<Vec<Spanned<Node>>, ParseError> {
+  // `while poll();` has an empty body; the `;` is left to end the statement
+  if peek_token(it, Token::Semi) {
+    return Ok(vec![]);
+  }
+
+  require_token(it, Token::Enter)?;
+  let nodes = parse_stmts(it, Token::Exit);
   require_token(it, Token::Exit)?;
 
   Ok(nodes)
 }
 
-pub fn parse(tokens: Vec<Spanned<Token>>) -> Parse {
-  let mut it: ParseIter = tokens.iter().peekable();
-  let mut nodes: Vec<Node> = vec![];
+// Parse a whole token stream, as produced by `lexer::lex`, into a Block
+//
+// Syntax errors don't stop the parser: each broken statement is replaced with
+// an Error node and parsing carries on, so this returns the partial AST along
+// with every error found. The only thing it can't recover from is a stream
+// without even an EOF token, since then there's nothing to span the AST
+pub fn parse(tokens: Vec<Spanned<Token>>) -> Result<(Spanned<Node>, Vec<ParseError>), ParseError> {
+  let (first, last) = match (tokens.first(), tokens.last()) {
+    (Some(first), Some(last)) => (first.span, last.span),
+    _ => {
+      return Err(ParseError::new(
+        UnexpectedEOF,
+        None,
+        vec![Expected::Token(Token::EOF)],
+      ))
+    }
+  };
+
+  let mut it = ParseIter::new(&tokens);
+  let nodes = parse_stmts(&mut it, Token::EOF);
 
-  while !peek_token(&mut it, Token::EOF) {
-    let stmt = parse_stmt(&mut it)?;
-    nodes.push(stmt);
-    require_token(&mut it, Token::End)?;
+  if let Err(err) = require_token(&mut it, Token::EOF) {
+    it.errors.push(err);
   }
 
-  Ok(Node::Block(nodes))
+  let root = Spanned {
+    node: Node::Block(nodes),
+    span: first.merge(last),
+  };
+
+  Ok((root, it.errors))
 }
 
 #[cfg(test)]
 #[path = "./tests/parser.rs"]
-mod tests;
+pub(crate) mod tests;
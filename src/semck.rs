@@ -1,44 +1,141 @@
+use codemap::Spanned;
 use parser::Node;
 use parser::Place;
+use parser::Var;
+use std::collections::HashSet;
+use std::fmt;
 
-type Check = Result<(), CheckErrorKind>;
+type Check = Result<(), CheckError>;
+
+pub type CheckError = Spanned<CheckErrorKind>;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum CheckErrorKind {
   NotInLoop,
-  MissingIf,
   NotPlace,
+  UndefinedSave(String),
+  SaveNotTopLevel,
+}
+
+impl fmt::Display for CheckErrorKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      CheckErrorKind::NotInLoop => write!(f, "`break` or `continue` outside of a loop"),
+      CheckErrorKind::NotPlace => write!(f, "can't assign to this expression"),
+      CheckErrorKind::UndefinedSave(ref x) => {
+        write!(
+          f,
+          "can't save `{}`, which isn't defined at the top level",
+          x
+        )
+      }
+      CheckErrorKind::SaveNotTopLevel => write!(f, "`save` is only allowed at the top level"),
+    }
+  }
+}
+
+// Pin an error to the node it's about
+fn fail(kind: CheckErrorKind, node: &Spanned<Node>) -> Check {
+  Err(Spanned {
+    node: kind,
+    span: node.span,
+  })
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct SemChecker {
   in_loop: bool,
-  has_if: bool,
+  // names bound at the top level of the module, which are the only ones that
+  // can be saved
+  globals: HashSet<String>,
+}
+
+impl Default for SemChecker {
+  fn default() -> SemChecker {
+    SemChecker::new()
+  }
 }
 
 impl SemChecker {
   pub fn new() -> SemChecker {
     SemChecker {
       in_loop: false,
-      has_if: false,
+      globals: HashSet::new(),
     }
   }
 
-  pub fn check(&mut self, node: &mut Node) -> Check {
-    println!("checking: {:?}", node);
-    match *node {
+  pub fn check(&mut self, node: &mut Spanned<Node>) -> Check {
+    println!("checking: {:?}", node.node);
+    match node.node {
       Node::Stmt(ref mut bx) => {
         self.check(bx)?;
       }
 
-      Node::Block(ref mut ls) | Node::Catch(ref mut ls) => for mut n in ls {
-        self.check(&mut n)?;
-      },
+      // the module itself, which is the only place `save` is allowed
+      Node::Block(ref mut ls) => {
+        for n in ls.iter() {
+          self.define(n);
+        }
+
+        for n in ls {
+          match n.node {
+            Node::Save { ref name, .. } if !self.globals.contains(name) => {
+              return fail(CheckErrorKind::UndefinedSave(name.clone()), n);
+            }
+            Node::Save {
+              rhs: Some(ref mut rhs),
+              ..
+            } => self.check(rhs)?,
+            Node::Save { rhs: None, .. } => {}
+            _ => self.check(n)?,
+          }
+        }
+      }
+
+      Node::Catch(ref mut ls) => {
+        for n in ls {
+          self.check(n)?;
+        }
+      }
+
+      Node::Save { .. } => {
+        return fail(CheckErrorKind::SaveNotTopLevel, node);
+      }
+
+      // a function body is its own world; a loop around the function doesn't
+      // make `break` inside it valid
+      Node::Func { ref mut body, .. } => {
+        let in_loop = self.in_loop;
+        self.in_loop = false;
+        for n in body {
+          self.check(n)?;
+        }
+        self.in_loop = in_loop;
+      }
+
+      Node::If {
+        ref mut body,
+        ref mut els,
+        cond: _,
+      } => {
+        for n in body {
+          self.check(n)?;
+        }
+        if let Some(ref mut els) = *els {
+          self.check(els)?;
+        }
+      }
+
+      Node::Else { ref mut body } => {
+        for n in body {
+          self.check(n)?;
+        }
+      }
 
       Node::Loop { ref mut body } => {
         self.in_loop = true;
-        for mut n in body {
-          self.check(&mut n)?;
+        for n in body {
+          self.check(n)?;
         }
         self.in_loop = false;
       }
@@ -48,8 +145,8 @@ impl SemChecker {
         expr: _,
       } => {
         self.in_loop = true;
-        for mut n in body {
-          self.check(&mut n)?;
+        for n in body {
+          self.check(n)?;
         }
         self.in_loop = false;
       }
@@ -60,48 +157,100 @@ impl SemChecker {
         expr: _,
       } => {
         self.in_loop = true;
-        for mut n in body {
-          self.check(&mut n)?;
+        for n in body {
+          self.check(n)?;
         }
         self.in_loop = false;
       }
 
-      Node::Break | Node::Continue => {
-        if !self.in_loop {
-          return Err(CheckErrorKind::NotInLoop);
-        }
+      Node::Break | Node::Continue if !self.in_loop => {
+        return fail(CheckErrorKind::NotInLoop, node);
       }
 
-      Node::Assn { rhs: _, ref lhs } => {
+      Node::Assn { ref lhs, .. } => {
         self.check_place(lhs)?;
       }
 
-      // TODO add if-elif-else checks
       _ => {}
     }
 
     Ok(())
   }
 
-  fn check_place(&self, place: &Place) -> Check {
-    match *place {
+  // Record any names bound by a top-level statement
+  fn define(&mut self, node: &Spanned<Node>) {
+    match node.node {
+      Node::Assn { ref lhs, .. } => self.define_place(lhs),
+      Node::Decl { ref decl, .. } => self.define_var(decl),
+      Node::Import {
+        ref path,
+        ref alias,
+      } => {
+        if let Some(name) = alias.as_ref().or_else(|| path.last()) {
+          self.globals.insert(name.clone());
+        }
+      }
+      Node::Save {
+        ref name,
+        rhs: Some(_),
+        ..
+      } => {
+        self.globals.insert(name.clone());
+      }
+      _ => {}
+    }
+  }
+
+  fn define_place(&mut self, place: &Spanned<Place>) {
+    match place.node {
+      Place::Single(ref node) => {
+        if let Node::Name(ref name) = node.node {
+          self.globals.insert(name.clone());
+        }
+      }
+      Place::Multi(ref places) => {
+        for pl in places {
+          self.define_place(pl);
+        }
+      }
+    }
+  }
+
+  fn define_var(&mut self, var: &Spanned<Var>) {
+    match var.node {
+      Var::Single(ref name) => {
+        self.globals.insert(name.clone());
+      }
+      Var::Multi(ref vars) => {
+        for v in vars {
+          self.define_var(v);
+        }
+      }
+    }
+  }
+
+  fn check_place(&self, place: &Spanned<Place>) -> Check {
+    match place.node {
       Place::Single(ref node) => {
         self.is_place(node)?;
       }
       Place::Multi(ref places) => {
-        let mut valid = true;
         for pl in places {
-          self.check_place(&pl)?;
+          self.check_place(pl)?;
         }
       }
     };
     Ok(())
   }
 
-  fn is_place(&self, node: &Node) -> Check {
-    match *node {
+  fn is_place(&self, node: &Spanned<Node>) -> Check {
+    match node.node {
       Node::Name(_) | Node::Index { lhs: _, rhs: _ } => Ok(()),
-      _ => Err(CheckErrorKind::NotPlace),
+      _ => fail(CheckErrorKind::NotPlace, node),
     }
   }
 }
+
+#[cfg(test)]
+#[path = "./tests/semck.rs"]
+mod tests;
@@ -0,0 +1,87 @@
+use super::*;
+use codemap::CodeMap;
+
+fn get_cst(source: &str) -> Cst {
+  let mut map = CodeMap::new();
+  let file = map.add_file(String::from("_test"), String::from(source));
+  build(&file).unwrap()
+}
+
+fn ws(x: &str) -> Trivia {
+  Trivia::Whitespace(String::from(x))
+}
+
+fn comment(x: &str) -> Trivia {
+  Trivia::Comment(String::from(x))
+}
+
+#[test]
+fn cst_round_trip() {
+  let sources = vec![
+    "",
+    "\n\n",
+    "x",
+    "x = 1\n",
+    "\n\n  # leading comment\n\nx = 1   \n\n\n",
+    "## Adds things
+add = fn(a, b) # trailing
+  #[ a block
+     #[ nested ]# comment ]#
+  return a + b ;
+
+if x   # why
+    y = [
+      1,  # one
+      2,
+    ]
+else
+    pass # done",
+    "if x\r\n\tpass\r\n",
+    "s = 'héllo ${ name }, 変数' + r\"raw\\n\" # コメント\n",
+    "while poll();   x = {a = 1, [b] = 2}",
+    "loop\n  if x\n    break\n\n\n# trailing comment",
+  ];
+
+  for source in sources {
+    assert_eq!(get_cst(source).to_string(), source);
+  }
+}
+
+#[test]
+fn cst_trivia() {
+  let cst = get_cst("x = 1 # one\n# two\n\ny #[ a ]#\n");
+  let tokens: Vec<(Vec<Trivia>, &str, Vec<Trivia>)> = cst
+    .tokens
+    .iter()
+    .map(|t| (t.leading.clone(), t.text.as_str(), t.trailing.clone()))
+    .collect();
+
+  assert_eq!(
+    tokens,
+    vec![
+      (vec![], "x", vec![ws(" ")]),
+      (vec![], "=", vec![ws(" ")]),
+      (vec![], "1", vec![ws(" "), comment("# one")]),
+      // the line break before a comment-only line isn't an End
+      (vec![ws("\n"), comment("# two"), ws("\n")], "\n", vec![]),
+      (vec![], "y", vec![ws(" "), comment("#[ a ]#")]),
+      (vec![], "\n", vec![]),
+      (vec![], "", vec![]),
+    ]
+  );
+
+  // the tokens are the same ones the parser gets
+  let kinds: Vec<Token> = cst.tokens.iter().map(|t| t.token.node.clone()).collect();
+  assert_eq!(
+    kinds,
+    vec![
+      Token::Name(String::from("x")),
+      Token::Ass,
+      Token::Int(1),
+      Token::End,
+      Token::Name(String::from("y")),
+      Token::End,
+      Token::EOF,
+    ]
+  );
+}
@@ -0,0 +1,347 @@
+use super::*;
+use codemap::CodeMap;
+use parser::tests::Unspan;
+
+fn fmt(source: &str) -> String {
+  let mut map = CodeMap::new();
+  let file = map.add_file(String::from("_test"), String::from(source));
+  format(&file).unwrap()
+}
+
+// The tree `source` parses to, without any spans
+fn ast(source: &str) -> Spanned<Node> {
+  let mut map = CodeMap::new();
+  let file = map.add_file(String::from("_test"), String::from(source));
+  let (root, errors) = parser::parse(lexer::lex(&file).unwrap()).unwrap();
+  assert_eq!(errors, vec![]);
+  root.unspan()
+}
+
+#[test]
+fn fmt_spacing() {
+  assert_eq!(fmt("x=1+2*3"), "x = 1 + 2 * 3\n");
+  assert_eq!(fmt("var [a,b]=f( 1 ,2 )"), "var [a, b] = f(1, 2)\n");
+  assert_eq!(
+    fmt("y = x:foo( a,b )[ 0 ] . bar"),
+    "y = x:foo(a, b)[0].bar\n"
+  );
+  assert_eq!(fmt("f = |a,b|a+b"), "f = |a, b| a + b\n");
+  assert_eq!(fmt("x = - a and ! b"), "x = -a and !b\n");
+  assert_eq!(fmt("import   a.b->c"), "import a.b -> c\n");
+  assert_eq!(
+    fmt("x = 0x10 + 1_000_000 + 1e3 + 2.5"),
+    "x = 0x10 + 1_000_000 + 1e3 + 2.5\n"
+  );
+  assert_eq!(
+    fmt("x = a['b'] + a['b c'] + a['if']"),
+    "x = a.b + a['b c'] + a['if']\n"
+  );
+  assert_eq!(
+    fmt("s = \"it's ${ name }\\n\" + r'\\$' + 'a\\tb'"),
+    "s = \"it's ${name}\\n\" + r'\\$' + 'a\\tb'\n"
+  );
+  assert_eq!(fmt("x = 1; y = 2"), "x = 1\ny = 2\n");
+}
+
+#[test]
+fn fmt_parens() {
+  let cases = vec![
+    ("x = (1 + 2) * 3", "x = (1 + 2) * 3\n"),
+    ("x = 1 + (2 * 3)", "x = 1 + 2 * 3\n"),
+    ("x = (a - b) - c", "x = a - b - c\n"),
+    ("x = a - (b - c)", "x = a - (b - c)\n"),
+    ("x = (a ^ b) ^ c", "x = (a ^ b) ^ c\n"),
+    ("x = a ^ (b ^ c)", "x = a ^ b ^ c\n"),
+    ("x = (a or b) and c", "x = (a or b) and c\n"),
+    ("x = (a and b) or c", "x = a and b or c\n"),
+    ("x = -(a + b)", "x = -(a + b)\n"),
+    ("x = (-a) ^ b", "x = -a ^ b\n"),
+    ("x = (-a).b + (a + b)(c)", "x = (-a).b + (a + b)(c)\n"),
+    ("x = ((a))", "x = a\n"),
+    ("x = (1)", "x = 1\n"),
+    ("x = ((2)) + 3", "x = 2 + 3\n"),
+    ("x = (\"s\") + ('a${b}')", "x = \"s\" + 'a${b}'\n"),
+    ("x = (1).foo + ((2.5)):bar()", "x = (1).foo + (2.5):bar()\n"),
+  ];
+
+  for (source, expected) in cases {
+    assert_eq!(fmt(source), expected);
+  }
+}
+
+#[test]
+fn fmt_blocks() {
+  let source = "
+## Adds things
+add=fn( a,b )
+      return a+b
+if x
+   pass
+else   if y
+   pass
+else
+   while poll();
+   loop
+     break
+x = catch
+  pass
+";
+
+  let expected = "## Adds things
+add = fn(a, b)
+  return a + b
+
+if x
+  pass
+else if y
+  pass
+else
+  while poll();
+  loop
+    break
+x = catch
+  pass
+";
+
+  assert_eq!(fmt(source), expected);
+}
+
+#[test]
+fn fmt_blank_lines() {
+  let source = "
+
+
+a = 1
+
+
+b = 2
+c = 3
+f = fn()
+  x = 1
+
+
+
+  y = 2
+g = fn()
+  pass
+d = 4
+";
+
+  let expected = "a = 1
+
+b = 2
+c = 3
+
+f = fn()
+  x = 1
+
+  y = 2
+
+g = fn()
+  pass
+
+d = 4
+";
+
+  assert_eq!(fmt(source), expected);
+  assert_eq!(fmt(""), "");
+  assert_eq!(fmt("\n\n"), "");
+}
+
+#[test]
+fn fmt_collections() {
+  assert_eq!(fmt("x = [ 1,2, ]"), "x = [1, 2]\n");
+  assert_eq!(
+    fmt("x = {a=1,[b]=2, ['c d'] = 3}"),
+    "x = {a = 1, [b] = 2, ['c d'] = 3}\n"
+  );
+  assert_eq!(fmt("x = [\n1, 2]"), "x = [\n  1,\n  2,\n]\n");
+  assert_eq!(
+    fmt("x = f([\n1], {})"),
+    "x = f(\n  [\n    1,\n  ],\n  {},\n)\n"
+  );
+  assert_eq!(fmt("x = a:b(\n1, 2)"), "x = a:b(\n  1,\n  2,\n)\n");
+
+  let long = "x = [aaaaaaaaaa, bbbbbbbbbb, cccccccccc, dddddddddd, eeeeeeeeee, ffffffffff, ggggg]";
+  assert_eq!(
+    fmt(long),
+    "x = [
+  aaaaaaaaaa,
+  bbbbbbbbbb,
+  cccccccccc,
+  dddddddddd,
+  eeeeeeeeee,
+  ffffffffff,
+  ggggg,
+]
+"
+  );
+
+  let long = "f(aaaaaaaaaa, bbbbbbbbbb, cccccccccc, dddddddddd, eeeeeeeeee, ffffffffff, ggggggg)";
+  assert_eq!(
+    fmt(long),
+    "f(
+  aaaaaaaaaa,
+  bbbbbbbbbb,
+  cccccccccc,
+  dddddddddd,
+  eeeeeeeeee,
+  ffffffffff,
+  ggggggg,
+)
+"
+  );
+
+  let nested = "if x
+  y = {
+      a = [1,
+        2], b = 3}
+";
+  assert_eq!(
+    fmt(nested),
+    "if x
+  y = {
+    a = [
+      1,
+      2,
+    ],
+    b = 3,
+  }
+"
+  );
+}
+
+#[test]
+fn fmt_comments() {
+  let source = "# header
+
+x = 1   # one
+#[ a block
+   comment ]#
+if x # why
+    # inside
+    y = [ # first
+      1,  # one
+      # before two
+      2
+      # after two
+    ]
+    # end of if
+# after if
+z = f(1, #[ inline ]# 2)
+## doc
+g = fn()
+  pass
+
+
+# the end
+";
+
+  let expected = "# header
+
+x = 1 # one
+#[ a block
+   comment ]#
+if x # why
+  # inside
+  y = [
+    # first
+    1, # one
+    # before two
+    2,
+    # after two
+  ]
+  # end of if
+# after if
+z = f(
+  1, #[ inline ]#
+  2,
+)
+
+## doc
+g = fn()
+  pass
+
+# the end
+";
+
+  assert_eq!(fmt(source), expected);
+
+  let source = "f(
+  1, # one
+  2, # two
+)
+";
+  assert_eq!(fmt(source), source);
+
+  let source = "## Saved
+save f = fn()
+  pass
+";
+  assert_eq!(fmt(source), source);
+
+  // `##` that doesn't document anything is kept like any other comment
+  let source = "## Section ##
+if x ## why
+  y = [
+    ## item
+    1,
+  ]
+  ## end of if
+";
+  assert_eq!(fmt(source), source);
+}
+
+#[test]
+fn fmt_idempotent() {
+  let sources = vec![
+    "x=1",
+    "# only a comment",
+    "
+## Adds things
+add = fn(a, b) # trailing
+  #[ a block
+     #[ nested ]# comment ]#
+  return a + b ;
+
+if x   # why
+    y = [
+      1,  # one
+      2,
+    ]
+else
+    pass # done",
+    "if x\r\n\tpass\r\n",
+    "s = 'héllo ${ name }, 変数' + r\"raw\\n\" # コメント\n",
+    "while poll();   x = {a = 1, [b] = 2}",
+    "loop\n  if x\n    break\n\n\n# trailing comment",
+    "x = f(a, #[ one ]# b, #[ two ]#\n  c) # three\ny = 1",
+    "x = [[1, 2], {a = [3,\n 4]}, |x| x ^ -(1 + 2) // 3 << 1]",
+    "  x = 1\ny = 2",
+    "for [k, v] in d\n  save k\nsave v = fn()\n  pass\nreturn",
+    "x = (\"a${b}\") + (1).foo + ((0x2)) + '${ (r'c') }'",
+  ];
+
+  for source in sources {
+    let once = fmt(source);
+    assert_eq!(fmt(&once), once, "formatting {:?}", source);
+    assert_eq!(ast(&once), ast(source), "formatting {:?}", source);
+  }
+}
+
+#[test]
+fn fmt_errors() {
+  let mut map = CodeMap::new();
+
+  let file = map.add_file(String::from("_test"), String::from("x = 'oops"));
+  match format(&file) {
+    Err(FormatError::Lex(_)) => {}
+    x => panic!("expected a lex error, got {:?}", x),
+  }
+
+  let file = map.add_file(String::from("_test"), String::from("x = = 1"));
+  match format(&file) {
+    Err(FormatError::Parse(ref errors)) if errors.len() == 1 => {}
+    x => panic!("expected a parse error, got {:?}", x),
+  }
+}
@@ -5,7 +5,16 @@ use codemap::CodeMap;
 fn get_tokens(source: &str) -> Vec<Spanned<Token>> {
   let mut map = CodeMap::new();
   let file = map.add_file(String::from("_test"), String::from(source));
-  lex(&file)
+  lex(&file).unwrap()
+}
+
+fn get_error(source: &str) -> (LexErrorKind, u64, u64) {
+  let mut map = CodeMap::new();
+  let file = map.add_file(String::from("_test"), String::from(source));
+  let err = lex(&file).unwrap_err();
+  let lo = err.span.low() - file.span.low();
+  let hi = err.span.high() - file.span.low();
+  (err.node, lo, hi)
 }
 
 #[test]
@@ -26,15 +35,48 @@ fn lex_numbers() {
   assert_eq!(tokens[9].node, EOF);
 }
 
+#[test]
+fn lex_number_formats() {
+  let source =
+    "0xff 0xDEAD_beef 0o17 0b1010 0b1111_0000 1_000_000 6.02e23 1e-3 2.5E+2 1_0.0_1 0x1e-1 .5";
+  let tokens: Vec<Token> = get_tokens(source).into_iter().map(|t| t.node).collect();
+  assert_eq!(
+    tokens,
+    vec![
+      Int(255),
+      Int(0xdead_beef),
+      Int(15),
+      Int(10),
+      Int(240),
+      Int(1_000_000),
+      Float(6.02e23),
+      Float(1e-3),
+      Float(250.0),
+      Float(10.01),
+      Int(0x1e),
+      Sub,
+      Int(1),
+      Dot,
+      Int(5),
+      End,
+      EOF,
+    ]
+  );
+
+  let max = get_tokens("0x7fff_ffff_ffff_ffff 9223372036854775807");
+  assert_eq!(max[0].node, Int(i64::MAX));
+  assert_eq!(max[1].node, Int(i64::MAX));
+}
+
 #[test]
 fn lex_string() {
   let source = "'hello' 'this\\nis\\nmultiline' 'this\\\\is\\\\escaped' 'this\\tis\\ttabbed' 'this
 is
 real
-multiline' 'and this is unclosed";
+multiline'";
   let tokens = get_tokens(source);
 
-  assert_eq!(tokens.len(), 8);
+  assert_eq!(tokens.len(), 7);
   assert_eq!(tokens[0].node, Str(String::from("hello")));
   assert_eq!(tokens[1].node, Str(String::from("this\nis\nmultiline")));
   assert_eq!(tokens[2].node, Str(String::from("this\\is\\escaped")));
@@ -43,19 +85,278 @@ multiline' 'and this is unclosed";
     tokens[4].node,
     Str(String::from("this\nis\nreal\nmultiline"))
   );
+  assert_eq!(tokens[5].node, End);
+  assert_eq!(tokens[6].node, EOF);
+}
+
+#[test]
+fn lex_string_escapes() {
+  let source = r#"'\'quoted\'' "double 'and' \"escaped\"" '\0\x41\x7f' "\u{1F600}\u{e9}" r'C:\dir\n' r"^\d+\.\w*$" ''"#;
+  let tokens: Vec<Token> = get_tokens(source).into_iter().map(|t| t.node).collect();
   assert_eq!(
-    tokens[5].node,
-    UnclosedStr(String::from("and this is unclosed"))
+    tokens,
+    vec![
+      Str(String::from("'quoted'")),
+      Str(String::from("double 'and' \"escaped\"")),
+      Str(String::from("\0A\x7f")),
+      Str(String::from("\u{1F600}\u{e9}")),
+      Str(String::from("C:\\dir\\n")),
+      Str(String::from("^\\d+\\.\\w*$")),
+      Str(String::new()),
+      End,
+      EOF,
+    ]
+  );
+
+  // `r` on its own is still a name
+  let tokens = get_tokens("r r2 'x'");
+  assert_eq!(tokens[0].node, Name(String::from("r")));
+  assert_eq!(tokens[1].node, Name(String::from("r2")));
+  assert_eq!(tokens[2].node, Str(String::from("x")));
+}
+
+#[test]
+fn lex_interp() {
+  let mut map = CodeMap::new();
+  let file = map.add_file(
+    String::from("_test"),
+    String::from("'a ${b + 1} c ${d}' '${}' '$ \\${e}' r'${f}'"),
+  );
+  let tokens = lex(&file).unwrap();
+
+  // every span, including those of the embedded tokens, points back into the
+  // original source
+  let frags = |tok: &Spanned<Token>| -> Vec<(String, Vec<(Token, &str)>)> {
+    match tok.node {
+      Interp(ref parts) => parts
+        .iter()
+        .map(|part| match part.node {
+          Fragment::Str(ref x) => (x.clone(), vec![]),
+          Fragment::Expr(ref toks) => (
+            file.source_slice(part.span).to_string(),
+            toks
+              .iter()
+              .map(|t| (t.node.clone(), file.source_slice(t.span)))
+              .collect(),
+          ),
+        })
+        .collect(),
+      _ => panic!("expected an interpolated string, found {:?}", tok.node),
+    }
+  };
+
+  assert_eq!(
+    frags(&tokens[0]),
+    vec![
+      (String::from("a "), vec![]),
+      (
+        String::from("${b + 1}"),
+        vec![
+          (Name(String::from("b")), "b"),
+          (Add, "+"),
+          (Int(1), "1"),
+          (Cur, "}"),
+        ],
+      ),
+      (String::from(" c "), vec![]),
+      (
+        String::from("${d}"),
+        vec![(Name(String::from("d")), "d"), (Cur, "}")],
+      ),
+    ]
+  );
+  assert_eq!(
+    frags(&tokens[1]),
+    vec![(String::from("${}"), vec![(Cur, "}")])]
+  );
+  assert_eq!(tokens[2].node, Str(String::from("$ ${e}")));
+  assert_eq!(tokens[3].node, Str(String::from("${f}")));
+
+  assert_eq!(get_error("'${x'"), (UnclosedStr, 0, 5));
+  assert_eq!(get_error("'${x ? y}'"), (UnknownChar('?'), 5, 6));
+  assert_eq!(get_error("'abc ${a ? b} def'"), (UnknownChar('?'), 9, 10));
+  assert_eq!(get_error("'${'\\q'}'"), (UnknownEscape('q'), 3, 6));
+}
+
+#[test]
+fn lex_comments() {
+  let source = "x = #[ inline ]# 1
+#[ spans
+   #[ nested ]#
+   lines ]# y
+##  documented
+##
+f # not ##
+#not[ a block";
+  let tokens: Vec<Token> = get_tokens(source).into_iter().map(|t| t.node).collect();
+  assert_eq!(
+    tokens,
+    vec![
+      Name(String::from("x")),
+      Ass,
+      Int(1),
+      End,
+      Name(String::from("y")),
+      End,
+      Doc(String::from(" documented")),
+      End,
+      Doc(String::new()),
+      End,
+      Name(String::from("f")),
+      End,
+      EOF,
+    ]
+  );
+
+  // a line with only a comment on it doesn't open or close a block
+  let source = "if x
+    # deeper
+  #[ a
+block ]#
+  y
+# shallower
+  z";
+  let tokens: Vec<Token> = get_tokens(source).into_iter().map(|t| t.node).collect();
+  assert_eq!(
+    tokens,
+    vec![
+      If,
+      Name(String::from("x")),
+      Enter,
+      Name(String::from("y")),
+      End,
+      Name(String::from("z")),
+      End,
+      Exit,
+      End,
+      EOF,
+    ]
+  );
+
+  // `##` only documents something when it starts a line outside brackets
+  let source = "x = 1 ## note
+y = [
+  ## item
+  2,
+]
+  ## indented";
+  let tokens: Vec<Token> = get_tokens(source).into_iter().map(|t| t.node).collect();
+  assert_eq!(
+    tokens,
+    vec![
+      Name(String::from("x")),
+      Ass,
+      Int(1),
+      End,
+      Name(String::from("y")),
+      Ass,
+      Sql,
+      Int(2),
+      Com,
+      Sqr,
+      Enter,
+      Doc(String::from("indented")),
+      End,
+      Exit,
+      End,
+      EOF,
+    ]
+  );
+
+  let mut map = CodeMap::new();
+  let file = map.add_file(String::from("_test"), String::from(source));
+  let (_tokens, comments) = lex_lossless(&file).unwrap();
+  let comments: Vec<Token> = comments.into_iter().map(|t| t.node).collect();
+  assert_eq!(
+    comments,
+    vec![
+      Comment(String::from("# note")),
+      Comment(String::from("# item")),
+    ]
+  );
+}
+
+#[test]
+fn lex_errors() {
+  assert_eq!(get_error("x = 'unclosed"), (UnclosedStr, 4, 13));
+  assert_eq!(get_error("x #[ a #[ b ]#\n"), (UnclosedComment, 2, 15));
+  assert_eq!(get_error("x = \"unclosed'"), (UnclosedStr, 4, 14));
+  assert_eq!(get_error("r'unclosed"), (UnclosedStr, 0, 10));
+  assert_eq!(get_error("'a\\qb'"), (UnknownEscape('q'), 0, 4));
+  assert_eq!(get_error("'C:\\dir'"), (UnknownEscape('d'), 0, 5));
+
+  let malformed = vec![
+    ("'\\x4g'", "\\x4g"),
+    ("'\\x80'", "\\x80"),
+    ("'\\x4'", "\\x4'"),
+    ("'\\u41'", "\\u"),
+    ("'\\u{}'", "\\u{}"),
+    ("'\\u{110000}'", "\\u{110000}"),
+    ("'\\u{d800}'", "\\u{d800}"),
+    ("'\\u{1234567}'", "\\u{123456"),
+    ("'\\u{12'", "\\u{12"),
+  ];
+  for (source, escape) in malformed {
+    assert_eq!(get_error(source).0, MalformedEscape(String::from(escape)));
+  }
+  assert_eq!(
+    get_error("x = 1.2.3"),
+    (MalformedNumber(String::from("1.2.3")), 4, 9)
+  );
+  assert_eq!(
+    get_error("99999999999999999999"),
+    (IntegerOverflow(String::from("99999999999999999999")), 0, 20)
+  );
+  assert_eq!(get_error("x = y ? z"), (UnknownChar('?'), 6, 7));
+
+  let malformed = vec![
+    "0x", "0xg", "0b102", "0o8", "0X1", "1__0", "1_", "0x_1", "1_.5", "1._5", "1e", "1e+", "1e5.0",
+    "1e_5", "5abc", "1.2e3e4",
+  ];
+  for x in malformed {
+    assert_eq!(
+      get_error(x),
+      (MalformedNumber(String::from(x)), 0, x.len() as u64)
+    );
+  }
+
+  assert_eq!(
+    get_error("0x8000_0000_0000_0000"),
+    (
+      IntegerOverflow(String::from("0x8000_0000_0000_0000")),
+      0,
+      21
+    )
+  );
+  assert_eq!(
+    get_error("x = 1e999"),
+    (FloatOverflow(String::from("1e999")), 4, 9)
+  );
+  assert_eq!(get_error("'日本' ?"), (UnknownChar('?'), 9, 10));
+  assert_eq!(get_error("x € y"), (UnknownChar('€'), 2, 5));
+  assert_eq!(get_error("f(1,\n  [2"), (UnclosedBracket(Sql), 7, 8));
+  assert_eq!(get_error("f(1,\n  {}"), (UnclosedBracket(Pal), 1, 2));
+  assert_eq!(get_error("if x\n \tpass"), (MixedIndent, 7, 11));
+  assert_eq!(get_error("if x\n    pass\n  y = 1\nz"), (BadDedent, 16, 21));
+  assert_eq!(
+    get_error("if x\n  if y\n      pass\n    pass"),
+    (BadDedent, 27, 31)
+  );
+  assert_eq!(
+    get_error("if x\n\tpass\nif y\n  pass"),
+    (InconsistentIndent(' '), 18, 22)
+  );
+  assert_eq!(
+    get_error("if x\n  pass\nif y\n\tpass"),
+    (InconsistentIndent('\t'), 18, 22)
   );
-  assert_eq!(tokens[6].node, End);
-  assert_eq!(tokens[7].node, EOF);
 }
 
 #[test]
 fn lex_keywords() {
-  let source = "break catch continue else for fn if import in loop pass return save var while name true false null";
+  let source = "break catch continue else for fn if import in loop pass return save var while name true false null and or";
   let tokens = get_tokens(source);
-  assert_eq!(tokens.len(), 21);
+  assert_eq!(tokens.len(), 23);
   assert_eq!(tokens[0].node, Break);
   assert_eq!(tokens[1].node, Catch);
   assert_eq!(tokens[2].node, Continue);
@@ -75,8 +376,10 @@ fn lex_keywords() {
   assert_eq!(tokens[16].node, Bool(true));
   assert_eq!(tokens[17].node, Bool(false));
   assert_eq!(tokens[18].node, Null);
-  assert_eq!(tokens[19].node, End);
-  assert_eq!(tokens[20].node, EOF);
+  assert_eq!(tokens[19].node, LogAnd);
+  assert_eq!(tokens[20].node, LogOr);
+  assert_eq!(tokens[21].node, End);
+  assert_eq!(tokens[22].node, EOF);
 }
 
 #[test]
@@ -127,6 +430,16 @@ fn lex_symbols() {
   assert_eq!(tokens[38].node, EOF);
 }
 
+#[test]
+fn lex_double_symbols() {
+  let source = "<< >> // <<< >>= ///";
+  let tokens: Vec<Token> = get_tokens(source).into_iter().map(|t| t.node).collect();
+  assert_eq!(
+    tokens,
+    vec![Shl, Shr, Fdiv, Shl, Lt, Shr, Ass, Fdiv, Div, End, EOF]
+  );
+}
+
 #[test]
 fn lex_structure() {
   // this test uses a single trailing hash to avoid trailing whitespace errors in git and editors
@@ -167,7 +480,7 @@ else
   "
     .replace("#\n", "\n");
   let tokens = get_tokens(source.as_str());
-  assert_eq!(tokens.len(), 64);
+  assert_eq!(tokens.len(), 61);
   assert_eq!(tokens[0].node, Pass);
   assert_eq!(tokens[1].node, End);
   assert_eq!(tokens[2].node, If);
@@ -223,15 +536,13 @@ else
   assert_eq!(tokens[52].node, Exit);
   assert_eq!(tokens[53].node, End);
   assert_eq!(tokens[54].node, Else);
+  // the comment line after `else` doesn't open a block of its own
   assert_eq!(tokens[55].node, Enter);
-  assert_eq!(tokens[56].node, Enter);
-  assert_eq!(tokens[57].node, Pass);
-  assert_eq!(tokens[58].node, End);
-  assert_eq!(tokens[59].node, Exit);
-  assert_eq!(tokens[60].node, End);
-  assert_eq!(tokens[61].node, Exit);
-  assert_eq!(tokens[62].node, End);
-  assert_eq!(tokens[63].node, EOF);
+  assert_eq!(tokens[56].node, Pass);
+  assert_eq!(tokens[57].node, End);
+  assert_eq!(tokens[58].node, Exit);
+  assert_eq!(tokens[59].node, End);
+  assert_eq!(tokens[60].node, EOF);
 }
 
 #[test]
@@ -249,3 +560,121 @@ fn lex_trailing_exits() {
   assert_eq!(tokens[6].node, End);
   assert_eq!(tokens[7].node, EOF);
 }
+
+#[test]
+fn lex_brackets() {
+  let kinds =
+    |source: &str| -> Vec<Token> { get_tokens(source).into_iter().map(|t| t.node).collect() };
+
+  assert_eq!(
+    kinds("f(1,\n      2)\nx"),
+    vec![
+      Name(String::from("f")),
+      Pal,
+      Int(1),
+      Com,
+      Int(2),
+      Par,
+      End,
+      Name(String::from("x")),
+      End,
+      EOF,
+    ]
+  );
+
+  // blank lines, comments and dedents are all fine inside brackets
+  assert_eq!(
+    kinds("x = [\n  {\n    a = 1, # one\n\n  },\n]"),
+    vec![
+      Name(String::from("x")),
+      Ass,
+      Sql,
+      Cul,
+      Name(String::from("a")),
+      Ass,
+      Int(1),
+      Com,
+      Cur,
+      Com,
+      Sqr,
+      End,
+      EOF,
+    ]
+  );
+
+  // indentation picks up again once every bracket is closed
+  assert_eq!(
+    kinds("if f(\n1)\n  pass\n"),
+    vec![
+      If,
+      Name(String::from("f")),
+      Pal,
+      Int(1),
+      Par,
+      Enter,
+      Pass,
+      End,
+      Exit,
+      End,
+      EOF,
+    ]
+  );
+}
+
+#[test]
+fn lex_tabs() {
+  let kinds =
+    |source: &str| -> Vec<Token> { get_tokens(source).into_iter().map(|t| t.node).collect() };
+
+  assert_eq!(
+    kinds("if x\n\tif y\n\t\tpass\n \t\n\tz =\t1"),
+    vec![
+      If,
+      Name(String::from("x")),
+      Enter,
+      If,
+      Name(String::from("y")),
+      Enter,
+      Pass,
+      End,
+      Exit,
+      End,
+      Name(String::from("z")),
+      Ass,
+      Int(1),
+      End,
+      Exit,
+      End,
+      EOF,
+    ]
+  );
+}
+
+#[test]
+fn lex_unicode() {
+  let source = "# コメント
+ñame = 'héllo' + 変数_2
+_x·y";
+  let mut map = CodeMap::new();
+  let file = map.add_file(String::from("_test"), String::from(source));
+  let tokens = lex(&file).unwrap();
+  let spans: Vec<(Token, &str)> = tokens
+    .iter()
+    .map(|t| (t.node.clone(), file.source_slice(t.span)))
+    .collect();
+
+  assert_eq!(
+    spans,
+    vec![
+      (Name(String::from("ñame")), "ñame"),
+      (Ass, "="),
+      (Str(String::from("héllo")), "'héllo'"),
+      (Add, "+"),
+      (Name(String::from("変数_2")), "変数_2"),
+      (End, "\n"),
+      (Name(String::from("_x·y")), "_x·y"),
+      (End, ""),
+      (EOF, ""),
+    ]
+  );
+}
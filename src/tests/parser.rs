@@ -1,6 +1,7 @@
 use super::*;
 use super::super::lexer;
 use codemap::CodeMap;
+use codemap::Span;
 use codemap::Spanned;
 use std::fmt::Debug;
 use std::cmp::PartialEq;
@@ -8,187 +9,371 @@ use std::cmp::PartialEq;
 fn get_tokens(source: &str) -> Vec<Spanned<Token>> {
   let mut map = CodeMap::new();
   let file = map.add_file(String::from("_test"), String::from(source));
-  lexer::lex(&file)
+  lexer::lex(&file).unwrap()
 }
 
-fn test_parse<T: Debug + PartialEq>(
+// every fresh CodeMap starts its first file at the same position, so this is
+// always the same span
+fn dummy_span() -> Span {
+  let mut map = CodeMap::new();
+  map.add_file(String::new(), String::new()).span
+}
+
+fn sp<T>(node: T) -> Spanned<T> {
+  Spanned {
+    node,
+    span: dummy_span(),
+  }
+}
+
+fn bx(node: Node) -> Box<Spanned<Node>> {
+  Box::new(sp(node))
+}
+
+// Replaces every span in a tree with `dummy_span()` so that the expected trees
+// in these tests don't need to spell out source positions
+pub(crate) trait Unspan {
+  fn unspan(self) -> Self;
+}
+
+impl<T: Unspan> Unspan for Vec<T> {
+  fn unspan(self) -> Self {
+    self.into_iter().map(Unspan::unspan).collect()
+  }
+}
+
+impl<T: Unspan> Unspan for Box<T> {
+  fn unspan(self) -> Self {
+    Box::new((*self).unspan())
+  }
+}
+
+impl<T: Unspan> Unspan for Option<T> {
+  fn unspan(self) -> Self {
+    self.map(Unspan::unspan)
+  }
+}
+
+impl Unspan for Spanned<Var> {
+  fn unspan(self) -> Self {
+    sp(match self.node {
+      Var::Single(x) => Var::Single(x),
+      Var::Multi(x) => Var::Multi(x.unspan()),
+    })
+  }
+}
+
+impl Unspan for Spanned<Place> {
+  fn unspan(self) -> Self {
+    sp(match self.node {
+      Place::Single(x) => Place::Single(x.unspan()),
+      Place::Multi(x) => Place::Multi(x.unspan()),
+    })
+  }
+}
+
+impl Unspan for Spanned<Node> {
+  fn unspan(self) -> Self {
+    sp(match self.node {
+      Node::Block(x) => Node::Block(x.unspan()),
+      Node::Stmt(x) => Node::Stmt(x.unspan()),
+      Node::Catch(x) => Node::Catch(x.unspan()),
+      Node::Assn { lhs, rhs, doc } => Node::Assn {
+        lhs: lhs.unspan(),
+        rhs: rhs.unspan(),
+        doc,
+      },
+      Node::Decl { decl, rhs, doc } => Node::Decl {
+        decl: decl.unspan(),
+        rhs: rhs.unspan(),
+        doc,
+      },
+      Node::If { cond, body, els } => Node::If {
+        cond: cond.unspan(),
+        body: body.unspan(),
+        els: els.unspan(),
+      },
+      Node::Else { body } => Node::Else {
+        body: body.unspan(),
+      },
+      Node::For { decl, expr, body } => Node::For {
+        decl: decl.unspan(),
+        expr: expr.unspan(),
+        body: body.unspan(),
+      },
+      Node::While { expr, body } => Node::While {
+        expr: expr.unspan(),
+        body: body.unspan(),
+      },
+      Node::Loop { body } => Node::Loop {
+        body: body.unspan(),
+      },
+      Node::Return(x) => Node::Return(x.unspan()),
+      Node::Save { name, rhs, doc } => Node::Save {
+        name,
+        rhs: rhs.unspan(),
+        doc,
+      },
+      Node::Index { lhs, rhs } => Node::Index {
+        lhs: lhs.unspan(),
+        rhs: rhs.unspan(),
+      },
+      Node::Method {
+        owner,
+        method,
+        args,
+      } => Node::Method {
+        owner: owner.unspan(),
+        method: method.unspan(),
+        args: args.unspan(),
+      },
+      Node::Func { params, body, doc } => Node::Func {
+        params,
+        body: body.unspan(),
+        doc,
+      },
+      Node::Lambda { params, expr } => Node::Lambda {
+        params,
+        expr: expr.unspan(),
+      },
+      Node::Call { func, args } => Node::Call {
+        func: func.unspan(),
+        args: args.unspan(),
+      },
+      Node::Array(x) => Node::Array(x.unspan()),
+      Node::Interp(x) => Node::Interp(x.unspan()),
+      Node::Dict(x) => Node::Dict(
+        x.into_iter()
+          .map(|(key, val)| (key.unspan(), val.unspan()))
+          .collect(),
+      ),
+      Node::BinExpr { lhs, op, rhs } => Node::BinExpr {
+        lhs: lhs.unspan(),
+        op,
+        rhs: rhs.unspan(),
+      },
+      Node::UnExpr { val, op } => Node::UnExpr {
+        val: val.unspan(),
+        op,
+      },
+      Node::LogicExpr { lhs, op, rhs } => Node::LogicExpr {
+        lhs: lhs.unspan(),
+        op,
+        rhs: rhs.unspan(),
+      },
+      leaf => leaf,
+    })
+  }
+}
+
+fn test_parse<T: Debug + PartialEq + Unspan>(
   source: &str,
-  func: &Fn(&mut ParseIter) -> Result<T, ParseErrorKind>,
+  func: &dyn Fn(&mut ParseIter) -> Result<T, ParseError>,
   expect: Result<T, ParseErrorKind>,
 ) {
   let tokens = get_tokens(source);
-  let mut it = tokens.iter().peekable();
+  let mut it = ParseIter::new(&tokens);
+  let parse = |it: &mut ParseIter| func(it).map_err(|err| err.kind);
 
-  assert_eq!(func(&mut it), expect);
+  assert_eq!(parse(&mut it).map(Unspan::unspan), expect);
 
-  assert_eq!(func(&mut it), Err(UnexpectedToken(lexer::Token::End)));
+  assert_eq!(parse(&mut it), Err(UnexpectedToken(lexer::Token::End)));
   it.next();
 
-  assert_eq!(func(&mut it), Err(UnexpectedToken(lexer::Token::EOF)));
+  assert_eq!(parse(&mut it), Err(UnexpectedToken(lexer::Token::EOF)));
   it.next();
 
-  assert_eq!(func(&mut it), Err(UnexpectedEOF));
+  assert_eq!(parse(&mut it), Err(UnexpectedEOF));
+}
+
+fn parse_ok(tokens: Vec<Spanned<Token>>) -> Spanned<Node> {
+  let (root, errors) = parse(tokens).unwrap();
+  assert_eq!(errors, vec![]);
+  root
+}
+
+fn parse_error(source: &str) -> ParseError {
+  parse(get_tokens(source)).unwrap().1.remove(0)
+}
+
+// Returns the offsets of `span` relative to the first token; the sources in
+// these tests have no leading whitespace, so that is the start of the file
+fn offsets(tokens: &[Spanned<Token>], span: Span) -> (u64, u64) {
+  let start = tokens[0].span.low();
+  (span.low() - start, span.high() - start)
 }
 
 #[test]
 fn test_quark() {
-  test_parse("null", &parse_quark, Ok(Node::Null));
-  test_parse("true", &parse_quark, Ok(Node::Bool(true)));
-  test_parse("false", &parse_quark, Ok(Node::Bool(false)));
-  test_parse("1.3", &parse_quark, Ok(Node::Float(1.3)));
-  test_parse("0.3", &parse_quark, Ok(Node::Float(0.3)));
-  test_parse("2", &parse_quark, Ok(Node::Int(2)));
-  test_parse("3", &parse_quark, Ok(Node::Int(3)));
-  test_parse("name", &parse_quark, Ok(Node::Name(String::from("name"))));
-  test_parse("table", &parse_quark, Ok(Node::Table));
+  test_parse("null", &parse_quark, Ok(sp(Node::Null)));
+  test_parse("true", &parse_quark, Ok(sp(Node::Bool(true))));
+  test_parse("false", &parse_quark, Ok(sp(Node::Bool(false))));
+  test_parse("1.3", &parse_quark, Ok(sp(Node::Float(1.3))));
+  test_parse("0.3", &parse_quark, Ok(sp(Node::Float(0.3))));
+  test_parse("2", &parse_quark, Ok(sp(Node::Int(2))));
+  test_parse("3", &parse_quark, Ok(sp(Node::Int(3))));
+  test_parse(
+    "name",
+    &parse_quark,
+    Ok(sp(Node::Name(String::from("name")))),
+  );
+  test_parse("table", &parse_quark, Ok(sp(Node::Table)));
 }
 
 #[test]
 fn test_atom() {
-  test_parse("null", &parse_atom, Ok(Node::Null));
+  test_parse("null", &parse_atom, Ok(sp(Node::Null)));
 
-  test_parse("(null)", &parse_atom, Ok(Node::Null));
+  test_parse("(null)", &parse_atom, Ok(sp(Node::Null)));
 }
 
 #[test]
 fn test_simple() {
-  test_parse("foo", &parse_simple, Ok(Node::Name(String::from("foo"))));
+  test_parse(
+    "foo",
+    &parse_bin_expr,
+    Ok(sp(Node::Name(String::from("foo")))),
+  );
   test_parse(
     "foo.bar",
-    &parse_simple,
-    Ok(Node::Index {
-      lhs: Box::new(Node::Name(String::from("foo"))),
-      rhs: Box::new(Node::Str(String::from("bar"))),
-    }),
+    &parse_bin_expr,
+    Ok(sp(Node::Index {
+      lhs: bx(Node::Name(String::from("foo"))),
+      rhs: bx(Node::Str(String::from("bar"))),
+    })),
   );
   test_parse(
     "foo[bar]",
-    &parse_simple,
-    Ok(Node::Index {
-      lhs: Box::new(Node::Name(String::from("foo"))),
-      rhs: Box::new(Node::Name(String::from("bar"))),
-    }),
+    &parse_bin_expr,
+    Ok(sp(Node::Index {
+      lhs: bx(Node::Name(String::from("foo"))),
+      rhs: bx(Node::Name(String::from("bar"))),
+    })),
   );
   test_parse(
     "foo()",
-    &parse_simple,
-    Ok(Node::Call {
-      func: Box::new(Node::Name(String::from("foo"))),
+    &parse_bin_expr,
+    Ok(sp(Node::Call {
+      func: bx(Node::Name(String::from("foo"))),
       args: Vec::new(),
-    }),
+    })),
   );
   test_parse(
     "foo:bar()",
-    &parse_simple,
-    Ok(Node::Method {
-      owner: Box::new(Node::Name(String::from("foo"))),
-      method: Box::new(Node::Str(String::from("bar"))),
+    &parse_bin_expr,
+    Ok(sp(Node::Method {
+      owner: bx(Node::Name(String::from("foo"))),
+      method: bx(Node::Str(String::from("bar"))),
       args: Vec::new(),
-    }),
+    })),
   );
   test_parse(
     "foo.bar()",
-    &parse_simple,
-    Ok(Node::Call {
-      func: Box::new(Node::Index {
-        lhs: Box::new(Node::Name(String::from("foo"))),
-        rhs: Box::new(Node::Str(String::from("bar"))),
+    &parse_bin_expr,
+    Ok(sp(Node::Call {
+      func: bx(Node::Index {
+        lhs: bx(Node::Name(String::from("foo"))),
+        rhs: bx(Node::Str(String::from("bar"))),
       }),
       args: Vec::new(),
-    }),
+    })),
   );
   test_parse(
     "foo.bar[baz]:qux()",
-    &parse_simple,
-    Ok(Node::Method {
-      owner: Box::new(Node::Index {
-        lhs: Box::new(Node::Index {
-          lhs: Box::new(Node::Name(String::from("foo"))),
-          rhs: Box::new(Node::Str(String::from("bar"))),
+    &parse_bin_expr,
+    Ok(sp(Node::Method {
+      owner: bx(Node::Index {
+        lhs: bx(Node::Index {
+          lhs: bx(Node::Name(String::from("foo"))),
+          rhs: bx(Node::Str(String::from("bar"))),
         }),
-        rhs: Box::new(Node::Name(String::from("baz"))),
+        rhs: bx(Node::Name(String::from("baz"))),
       }),
-      method: Box::new(Node::Str(String::from("qux"))),
+      method: bx(Node::Str(String::from("qux"))),
       args: Vec::new(),
-    }),
+    })),
   );
 }
 
 #[test]
 fn test_fn_args() {
-  test_parse("()", &parse_fn_args, Ok(Vec::new()));
+  test_parse(
+    "()",
+    &|it| parse_fn_args(it).map(|args| args.node),
+    Ok(Vec::new()),
+  );
   test_parse(
     "(x)",
-    &parse_fn_args,
-    Ok(vec![Node::Name(String::from("x"))]),
+    &|it| parse_fn_args(it).map(|args| args.node),
+    Ok(vec![sp(Node::Name(String::from("x")))]),
   );
   test_parse(
     "(x,)",
-    &parse_fn_args,
-    Ok(vec![Node::Name(String::from("x"))]),
+    &|it| parse_fn_args(it).map(|args| args.node),
+    Ok(vec![sp(Node::Name(String::from("x")))]),
   );
   test_parse(
     "(x,y)",
-    &parse_fn_args,
+    &|it| parse_fn_args(it).map(|args| args.node),
     Ok(vec![
-      Node::Name(String::from("x")),
-      Node::Name(String::from("y")),
+      sp(Node::Name(String::from("x"))),
+      sp(Node::Name(String::from("y"))),
     ]),
   );
   test_parse(
     "(x,y,)",
-    &parse_fn_args,
+    &|it| parse_fn_args(it).map(|args| args.node),
     Ok(vec![
-      Node::Name(String::from("x")),
-      Node::Name(String::from("y")),
+      sp(Node::Name(String::from("x"))),
+      sp(Node::Name(String::from("y"))),
     ]),
   );
 }
 
 #[test]
 fn test_un_expr() {
-  test_parse("5", &parse_un_expr, Ok(Node::Int(5)));
+  test_parse("5", &parse_bin_expr, Ok(sp(Node::Int(5))));
 
   test_parse(
     "foo()",
-    &parse_un_expr,
-    Ok(Node::Call {
-      func: Box::new(Node::Name(String::from("foo"))),
+    &parse_bin_expr,
+    Ok(sp(Node::Call {
+      func: bx(Node::Name(String::from("foo"))),
       args: Vec::new(),
-    }),
+    })),
   );
 
   test_parse(
     "-5",
-    &parse_un_expr,
-    Ok(Node::UnExpr {
+    &parse_bin_expr,
+    Ok(sp(Node::UnExpr {
       op: lexer::Token::Sub,
-      val: Box::new(Node::Int(5)),
-    }),
+      val: bx(Node::Int(5)),
+    })),
   );
 
   test_parse(
     "-foo.bar",
-    &parse_un_expr,
-    Ok(Node::UnExpr {
+    &parse_bin_expr,
+    Ok(sp(Node::UnExpr {
       op: lexer::Token::Sub,
-      val: Box::new(Node::Index {
-        lhs: Box::new(Node::Name(String::from("foo"))),
-        rhs: Box::new(Node::Str(String::from("bar"))),
+      val: bx(Node::Index {
+        lhs: bx(Node::Name(String::from("foo"))),
+        rhs: bx(Node::Str(String::from("bar"))),
       }),
-    }),
+    })),
   );
 
   test_parse(
     "!-5",
-    &parse_un_expr,
-    Ok(Node::UnExpr {
+    &parse_bin_expr,
+    Ok(sp(Node::UnExpr {
       op: lexer::Token::Not,
-      val: Box::new(Node::UnExpr {
+      val: bx(Node::UnExpr {
         op: lexer::Token::Sub,
-        val: Box::new(Node::Int(5)),
+        val: bx(Node::Int(5)),
       }),
-    }),
+    })),
   );
 }
 
@@ -197,35 +382,270 @@ fn test_bin_expr() {
   test_parse(
     "1 + 2",
     &parse_bin_expr,
-    Ok(Node::BinExpr {
-      lhs: Box::new(Node::Int(1)),
+    Ok(sp(Node::BinExpr {
+      lhs: bx(Node::Int(1)),
       op: lexer::Token::Add,
-      rhs: Box::new(Node::Int(2)),
-    }),
+      rhs: bx(Node::Int(2)),
+    })),
   );
 
   test_parse(
     "(1 + 2)",
     &parse_bin_expr,
-    Ok(Node::BinExpr {
-      lhs: Box::new(Node::Int(1)),
+    Ok(sp(Node::BinExpr {
+      lhs: bx(Node::Int(1)),
       op: lexer::Token::Add,
-      rhs: Box::new(Node::Int(2)),
-    }),
+      rhs: bx(Node::Int(2)),
+    })),
   );
 
   test_parse(
     "1 + 2 * 3",
     &parse_bin_expr,
-    Ok(Node::BinExpr {
-      lhs: Box::new(Node::Int(1)),
+    Ok(sp(Node::BinExpr {
+      lhs: bx(Node::Int(1)),
       op: lexer::Token::Add,
-      rhs: Box::new(Node::BinExpr {
-        lhs: Box::new(Node::Int(2)),
+      rhs: bx(Node::BinExpr {
+        lhs: bx(Node::Int(2)),
         op: lexer::Token::Mul,
-        rhs: Box::new(Node::Int(3)),
+        rhs: bx(Node::Int(3)),
+      }),
+    })),
+  );
+}
+
+// Parses `source` as an expression and writes it back out with every operation
+// parenthesized, so the shape of the tree is easy to compare
+fn group(source: &str) -> String {
+  fn show(node: &Spanned<Node>) -> String {
+    let sym = |op: &Token| op.to_string().replace('`', "");
+    match node.node {
+      Node::BinExpr {
+        ref lhs,
+        ref op,
+        ref rhs,
+      }
+      | Node::LogicExpr {
+        ref lhs,
+        ref op,
+        ref rhs,
+      } => format!("({} {} {})", show(lhs), sym(op), show(rhs)),
+      Node::UnExpr { ref val, ref op } => format!("({}{})", sym(op), show(val)),
+      Node::Index { ref lhs, ref rhs } => format!("{}[{}]", show(lhs), show(rhs)),
+      Node::Call { ref func, ref args } => {
+        let args: Vec<String> = args.iter().map(show).collect();
+        format!("{}({})", show(func), args.join(", "))
+      }
+      Node::Name(ref x) | Node::Str(ref x) => x.clone(),
+      Node::Int(x) => x.to_string(),
+      ref other => format!("{:?}", other),
+    }
+  }
+
+  let tokens = get_tokens(source);
+  let mut it = ParseIter::new(&tokens);
+  let expr = parse_bin_expr(&mut it).unwrap();
+  assert_eq!(it.next().map(|tok| tok.node.clone()), Some(Token::End));
+  show(&expr)
+}
+
+#[test]
+fn test_associativity() {
+  let left = vec![
+    "or", "and", "==", "!=", "<", "<=", ">", ">=", "|", "~", "&", "<<", ">>", "+", "-", "*", "/",
+    "%", "//",
+  ];
+
+  for op in left {
+    assert_eq!(
+      group(&format!("a {0} b {0} c", op)),
+      format!("((a {0} b) {0} c)", op)
+    );
+  }
+
+  assert_eq!(group("a ^ b ^ c"), "(a ^ (b ^ c))");
+}
+
+#[test]
+fn test_precedence() {
+  let cases = vec![
+    ("1 + 2 * 3 ^ 4 - 5", "((1 + (2 * (3 ^ 4))) - 5)"),
+    ("a ^ b ^ c * d", "((a ^ (b ^ c)) * d)"),
+    ("a * b ^ c ^ d", "(a * (b ^ (c ^ d)))"),
+    ("a - b * c + d", "((a - (b * c)) + d)"),
+    ("a / b - c / d", "((a / b) - (c / d))"),
+    ("(a + b) * c", "((a + b) * c)"),
+    ("a * (b + c) * d", "((a * (b + c)) * d)"),
+    ("a < b == c > d", "(((a < b) == c) > d)"),
+    ("a + b < c * d", "((a + b) < (c * d))"),
+    ("a or b and c", "(a or (b and c))"),
+    ("a and b or c and d", "((a and b) or (c and d))"),
+    ("a == b or c < d and e", "((a == b) or ((c < d) and e))"),
+    ("-a ^ b", "((-a) ^ b)"),
+    ("-a * -b", "((-a) * (-b))"),
+    ("!a and ~b", "((!a) and (~b))"),
+    ("--a - b", "((-(-a)) - b)"),
+    ("-f(x) + a.b[c]", "((-f(x)) + a[b][c])"),
+    ("a | b ~ c & d", "(a | (b ~ (c & d)))"),
+    ("a & b ~ c | d", "(((a & b) ~ c) | d)"),
+    ("a << 2 & b >> 3", "((a << 2) & (b >> 3))"),
+    ("a + 1 << b - 1", "((a + 1) << (b - 1))"),
+    ("a % b * c // d", "(((a % b) * c) // d)"),
+    ("a + b % c", "(a + (b % c))"),
+    ("a // b ^ c", "(a // (b ^ c))"),
+    ("a | b == c & d", "((a | b) == (c & d))"),
+    ("~a ~ b", "((~a) ~ b)"),
+    ("x and y | z", "(x and (y | z))"),
+    ("f(a + b, c) * d", "(f((a + b), c) * d)"),
+  ];
+
+  for (source, expect) in cases {
+    assert_eq!(group(source), expect, "parsing `{}`", source);
+  }
+}
+
+#[test]
+fn test_long_chain() {
+  let source = vec!["x"; 1000].join(" + ");
+  let expect = (1..1000).fold(String::from("x"), |acc, _| format!("({} + x)", acc));
+  assert_eq!(group(&source), expect);
+}
+
+#[test]
+fn test_comparisons() {
+  let ops = vec![
+    ("==", lexer::Token::Eql),
+    ("!=", lexer::Token::Ne),
+    ("<", lexer::Token::Lt),
+    ("<=", lexer::Token::Le),
+    (">", lexer::Token::Gt),
+    (">=", lexer::Token::Ge),
+  ];
+
+  for (sym, op) in ops {
+    test_parse(
+      &format!("x {} 3", sym),
+      &parse_bin_expr,
+      Ok(sp(Node::BinExpr {
+        lhs: bx(Node::Name(String::from("x"))),
+        op,
+        rhs: bx(Node::Int(3)),
+      })),
+    );
+  }
+
+  test_parse(
+    "a + 1 < b * 2",
+    &parse_bin_expr,
+    Ok(sp(Node::BinExpr {
+      lhs: bx(Node::BinExpr {
+        lhs: bx(Node::Name(String::from("a"))),
+        op: lexer::Token::Add,
+        rhs: bx(Node::Int(1)),
+      }),
+      op: lexer::Token::Lt,
+      rhs: bx(Node::BinExpr {
+        lhs: bx(Node::Name(String::from("b"))),
+        op: lexer::Token::Mul,
+        rhs: bx(Node::Int(2)),
+      }),
+    })),
+  );
+
+  test_parse(
+    "a * 2 >= b - 1",
+    &parse_bin_expr,
+    Ok(sp(Node::BinExpr {
+      lhs: bx(Node::BinExpr {
+        lhs: bx(Node::Name(String::from("a"))),
+        op: lexer::Token::Mul,
+        rhs: bx(Node::Int(2)),
+      }),
+      op: lexer::Token::Ge,
+      rhs: bx(Node::BinExpr {
+        lhs: bx(Node::Name(String::from("b"))),
+        op: lexer::Token::Sub,
+        rhs: bx(Node::Int(1)),
+      }),
+    })),
+  );
+
+  test_parse(
+    "a < b == c",
+    &parse_bin_expr,
+    Ok(sp(Node::BinExpr {
+      lhs: bx(Node::BinExpr {
+        lhs: bx(Node::Name(String::from("a"))),
+        op: lexer::Token::Lt,
+        rhs: bx(Node::Name(String::from("b"))),
+      }),
+      op: lexer::Token::Eql,
+      rhs: bx(Node::Name(String::from("c"))),
+    })),
+  );
+
+  test_parse(
+    "if x == 3
+       pass",
+    &parse_stmt,
+    Ok(sp(Node::If {
+      cond: bx(Node::BinExpr {
+        lhs: bx(Node::Name(String::from("x"))),
+        op: lexer::Token::Eql,
+        rhs: bx(Node::Int(3)),
       }),
-    }),
+      body: vec![sp(Node::Pass)],
+      els: None,
+    })),
+  );
+}
+
+#[test]
+fn test_logic_expr() {
+  test_parse(
+    "a or b and c",
+    &parse_bin_expr,
+    Ok(sp(Node::LogicExpr {
+      lhs: bx(Node::Name(String::from("a"))),
+      op: lexer::Token::LogOr,
+      rhs: bx(Node::LogicExpr {
+        lhs: bx(Node::Name(String::from("b"))),
+        op: lexer::Token::LogAnd,
+        rhs: bx(Node::Name(String::from("c"))),
+      }),
+    })),
+  );
+
+  test_parse(
+    "a and b or c",
+    &parse_bin_expr,
+    Ok(sp(Node::LogicExpr {
+      lhs: bx(Node::LogicExpr {
+        lhs: bx(Node::Name(String::from("a"))),
+        op: lexer::Token::LogAnd,
+        rhs: bx(Node::Name(String::from("b"))),
+      }),
+      op: lexer::Token::LogOr,
+      rhs: bx(Node::Name(String::from("c"))),
+    })),
+  );
+
+  test_parse(
+    "x < 1 or y > 2",
+    &parse_bin_expr,
+    Ok(sp(Node::LogicExpr {
+      lhs: bx(Node::BinExpr {
+        lhs: bx(Node::Name(String::from("x"))),
+        op: lexer::Token::Lt,
+        rhs: bx(Node::Int(1)),
+      }),
+      op: lexer::Token::LogOr,
+      rhs: bx(Node::BinExpr {
+        lhs: bx(Node::Name(String::from("y"))),
+        op: lexer::Token::Gt,
+        rhs: bx(Node::Int(2)),
+      }),
+    })),
   );
 }
 
@@ -234,124 +654,445 @@ fn test_fn_expr() {
   test_parse(
     "|| 5",
     &parse_il_expr,
-    Ok(Node::Lambda {
+    Ok(sp(Node::Lambda {
       params: Vec::new(),
-      expr: Box::new(Node::Int(5)),
-    }),
+      expr: bx(Node::Int(5)),
+    })),
   );
 
   test_parse(
     "|x| 5",
     &parse_il_expr,
-    Ok(Node::Lambda {
+    Ok(sp(Node::Lambda {
       params: vec![String::from("x")],
-      expr: Box::new(Node::Int(5)),
-    }),
+      expr: bx(Node::Int(5)),
+    })),
   );
 
   test_parse(
     "|x,| 5",
     &parse_il_expr,
-    Ok(Node::Lambda {
+    Ok(sp(Node::Lambda {
       params: vec![String::from("x")],
-      expr: Box::new(Node::Int(5)),
-    }),
+      expr: bx(Node::Int(5)),
+    })),
   );
 
   test_parse(
     "|x,y| 5",
     &parse_il_expr,
-    Ok(Node::Lambda {
+    Ok(sp(Node::Lambda {
       params: vec![String::from("x"), String::from("y")],
-      expr: Box::new(Node::Int(5)),
-    }),
+      expr: bx(Node::Int(5)),
+    })),
+  );
+}
+
+#[test]
+fn test_array() {
+  test_parse("[]", &parse_il_expr, Ok(sp(Node::Array(vec![]))));
+
+  test_parse(
+    "[1]",
+    &parse_il_expr,
+    Ok(sp(Node::Array(vec![sp(Node::Int(1))]))),
+  );
+
+  test_parse(
+    "[1, 2,]",
+    &parse_il_expr,
+    Ok(sp(Node::Array(vec![sp(Node::Int(1)), sp(Node::Int(2))]))),
+  );
+
+  test_parse(
+    "[[x], y + 1, |z| z]",
+    &parse_il_expr,
+    Ok(sp(Node::Array(vec![
+      sp(Node::Array(vec![sp(Node::Name(String::from("x")))])),
+      sp(Node::BinExpr {
+        lhs: bx(Node::Name(String::from("y"))),
+        op: lexer::Token::Add,
+        rhs: bx(Node::Int(1)),
+      }),
+      sp(Node::Lambda {
+        params: vec![String::from("z")],
+        expr: bx(Node::Name(String::from("z"))),
+      }),
+    ]))),
+  );
+
+  test_parse(
+    "f([1, 2])",
+    &parse_il_expr,
+    Ok(sp(Node::Call {
+      func: bx(Node::Name(String::from("f"))),
+      args: vec![sp(Node::Array(vec![sp(Node::Int(1)), sp(Node::Int(2))]))],
+    })),
+  );
+
+  // an array is an operand like any other
+  test_parse(
+    "[1, 2] + y",
+    &parse_il_expr,
+    Ok(sp(Node::BinExpr {
+      lhs: bx(Node::Array(vec![sp(Node::Int(1)), sp(Node::Int(2))])),
+      op: lexer::Token::Add,
+      rhs: bx(Node::Name(String::from("y"))),
+    })),
+  );
+
+  test_parse(
+    "1 + [2]",
+    &parse_il_expr,
+    Ok(sp(Node::BinExpr {
+      lhs: bx(Node::Int(1)),
+      op: lexer::Token::Add,
+      rhs: bx(Node::Array(vec![sp(Node::Int(2))])),
+    })),
+  );
+
+  let index = Ok(sp(Node::Index {
+    lhs: bx(Node::Array(vec![sp(Node::Int(1)), sp(Node::Int(2))])),
+    rhs: bx(Node::Int(0)),
+  }));
+  test_parse("[1, 2][0]", &parse_il_expr, index.clone());
+  test_parse("([1, 2])[0]", &parse_il_expr, index);
+}
+
+#[test]
+fn test_dict() {
+  test_parse("{}", &parse_il_expr, Ok(sp(Node::Dict(vec![]))));
+
+  test_parse(
+    "{x = 1}",
+    &parse_il_expr,
+    Ok(sp(Node::Dict(vec![(
+      sp(Node::Str(String::from("x"))),
+      sp(Node::Int(1)),
+    )]))),
+  );
+
+  test_parse(
+    "{x = 1, [y] = 2,}",
+    &parse_il_expr,
+    Ok(sp(Node::Dict(vec![
+      (sp(Node::Str(String::from("x"))), sp(Node::Int(1))),
+      (sp(Node::Name(String::from("y"))), sp(Node::Int(2))),
+    ]))),
+  );
+
+  test_parse(
+    "{[1 + 2] = {a = [b]}, f = |x| x}",
+    &parse_il_expr,
+    Ok(sp(Node::Dict(vec![
+      (
+        sp(Node::BinExpr {
+          lhs: bx(Node::Int(1)),
+          op: lexer::Token::Add,
+          rhs: bx(Node::Int(2)),
+        }),
+        sp(Node::Dict(vec![(
+          sp(Node::Str(String::from("a"))),
+          sp(Node::Array(vec![sp(Node::Name(String::from("b")))])),
+        )])),
+      ),
+      (
+        sp(Node::Str(String::from("f"))),
+        sp(Node::Lambda {
+          params: vec![String::from("x")],
+          expr: bx(Node::Name(String::from("x"))),
+        }),
+      ),
+    ]))),
+  );
+
+  // a dict is an operand like any other
+  test_parse(
+    "{a = 1}.a",
+    &parse_il_expr,
+    Ok(sp(Node::Index {
+      lhs: bx(Node::Dict(vec![(
+        sp(Node::Str(String::from("a"))),
+        sp(Node::Int(1)),
+      )])),
+      rhs: bx(Node::Str(String::from("a"))),
+    })),
+  );
+
+  test_parse(
+    "x == {}",
+    &parse_il_expr,
+    Ok(sp(Node::BinExpr {
+      lhs: bx(Node::Name(String::from("x"))),
+      op: lexer::Token::Eql,
+      rhs: bx(Node::Dict(vec![])),
+    })),
+  );
+
+  let err = parse_error("t = {x 1}");
+  assert_eq!(
+    err.message,
+    "expected `=` after dict key, found integer `1`"
+  );
+
+  let err = parse_error("t = {1 = 1}");
+  assert_eq!(err.message, "expected one of `[`, name, found integer `1`");
+}
+
+#[test]
+fn test_array_stmt() {
+  // an array literal statement is just an expression...
+  test_parse(
+    "[a, b]",
+    &parse_stmt,
+    Ok(sp(Node::Stmt(bx(Node::Array(vec![
+      sp(Node::Name(String::from("a"))),
+      sp(Node::Name(String::from("b"))),
+    ]))))),
+  );
+
+  // ...until it's assigned to
+  test_parse(
+    "[a, [b, c],] = [1, [2, 3]]",
+    &parse_stmt,
+    Ok(sp(Node::Assn {
+      lhs: sp(Place::Multi(vec![
+        sp(Place::Single(bx(Node::Name(String::from("a"))))),
+        sp(Place::Multi(vec![
+          sp(Place::Single(bx(Node::Name(String::from("b"))))),
+          sp(Place::Single(bx(Node::Name(String::from("c"))))),
+        ])),
+      ])),
+      rhs: bx(Node::Array(vec![
+        sp(Node::Int(1)),
+        sp(Node::Array(vec![sp(Node::Int(2)), sp(Node::Int(3))])),
+      ])),
+      doc: None,
+    })),
   );
 }
 
 #[test]
 fn test_decl() {
-  test_parse("x", &parse_decl, Ok(Var::Single(String::from("x"))));
+  test_parse("x", &parse_decl, Ok(sp(Var::Single(String::from("x")))));
 
   test_parse(
     "[x]",
     &parse_decl,
-    Ok(Var::Multi(vec![Var::Single(String::from("x"))])),
+    Ok(sp(Var::Multi(vec![sp(Var::Single(String::from("x")))]))),
   );
 
   test_parse(
     "[x, y]",
     &parse_decl,
-    Ok(Var::Multi(vec![
-      Var::Single(String::from("x")),
-      Var::Single(String::from("y")),
-    ])),
+    Ok(sp(Var::Multi(vec![
+      sp(Var::Single(String::from("x"))),
+      sp(Var::Single(String::from("y"))),
+    ]))),
   );
 
   test_parse(
     "[[x, y], z]",
     &parse_decl,
-    Ok(Var::Multi(vec![
-      Var::Multi(vec![
-        Var::Single(String::from("x")),
-        Var::Single(String::from("y")),
-      ]),
-      Var::Single(String::from("z")),
-    ])),
+    Ok(sp(Var::Multi(vec![
+      sp(Var::Multi(vec![
+        sp(Var::Single(String::from("x"))),
+        sp(Var::Single(String::from("y"))),
+      ])),
+      sp(Var::Single(String::from("z"))),
+    ]))),
   );
 
   test_parse(
     "[x, [y, z]]",
     &parse_decl,
-    Ok(Var::Multi(vec![
-      Var::Single(String::from("x")),
-      Var::Multi(vec![
-        Var::Single(String::from("y")),
-        Var::Single(String::from("z")),
-      ]),
-    ])),
+    Ok(sp(Var::Multi(vec![
+      sp(Var::Single(String::from("x"))),
+      sp(Var::Multi(vec![
+        sp(Var::Single(String::from("y"))),
+        sp(Var::Single(String::from("z"))),
+      ])),
+    ]))),
   );
 
   test_parse(
     "[[x], [y]]",
     &parse_decl,
-    Ok(Var::Multi(vec![
-      Var::Multi(vec![Var::Single(String::from("x"))]),
-      Var::Multi(vec![Var::Single(String::from("y"))]),
-    ])),
+    Ok(sp(Var::Multi(vec![
+      sp(Var::Multi(vec![sp(Var::Single(String::from("x")))])),
+      sp(Var::Multi(vec![sp(Var::Single(String::from("y")))])),
+    ]))),
   );
 
   test_parse(
     "[x, [y, z], q]",
     &parse_decl,
-    Ok(Var::Multi(vec![
-      Var::Single(String::from("x")),
-      Var::Multi(vec![
-        Var::Single(String::from("y")),
-        Var::Single(String::from("z")),
-      ]),
-      Var::Single(String::from("q")),
-    ])),
+    Ok(sp(Var::Multi(vec![
+      sp(Var::Single(String::from("x"))),
+      sp(Var::Multi(vec![
+        sp(Var::Single(String::from("y"))),
+        sp(Var::Single(String::from("z"))),
+      ])),
+      sp(Var::Single(String::from("q"))),
+    ]))),
+  );
+}
+
+#[test]
+fn test_var_stmt() {
+  test_parse(
+    "var x = 1",
+    &parse_stmt,
+    Ok(sp(Node::Decl {
+      decl: sp(Var::Single(String::from("x"))),
+      rhs: bx(Node::Int(1)),
+      doc: None,
+    })),
+  );
+
+  test_parse(
+    "var [a, [b, c]] = f()",
+    &parse_stmt,
+    Ok(sp(Node::Decl {
+      decl: sp(Var::Multi(vec![
+        sp(Var::Single(String::from("a"))),
+        sp(Var::Multi(vec![
+          sp(Var::Single(String::from("b"))),
+          sp(Var::Single(String::from("c"))),
+        ])),
+      ])),
+      rhs: bx(Node::Call {
+        func: bx(Node::Name(String::from("f"))),
+        args: Vec::new(),
+      }),
+      doc: None,
+    })),
+  );
+
+  test_parse(
+    "var f = fn()
+       pass",
+    &parse_stmt,
+    Ok(sp(Node::Decl {
+      decl: sp(Var::Single(String::from("f"))),
+      rhs: bx(Node::Func {
+        params: Vec::new(),
+        body: vec![sp(Node::Pass)],
+        doc: None,
+      }),
+      doc: None,
+    })),
+  );
+
+  let err = parse_error("var x");
+  assert_eq!(
+    err.message,
+    "expected `=` after variable declaration, found end of line"
+  );
+
+  let err = parse_error("var x.y = 1");
+  assert_eq!(
+    err.message,
+    "expected `=` after variable declaration, found `.`"
+  );
+}
+
+#[test]
+fn test_import_stmt() {
+  test_parse(
+    "import a",
+    &parse_stmt,
+    Ok(sp(Node::Import {
+      path: vec![String::from("a")],
+      alias: None,
+    })),
+  );
+
+  test_parse(
+    "import a.b.c",
+    &parse_stmt,
+    Ok(sp(Node::Import {
+      path: vec![String::from("a"), String::from("b"), String::from("c")],
+      alias: None,
+    })),
+  );
+
+  test_parse(
+    "import a.b -> c",
+    &parse_stmt,
+    Ok(sp(Node::Import {
+      path: vec![String::from("a"), String::from("b")],
+      alias: Some(String::from("c")),
+    })),
   );
+
+  let tokens = get_tokens("import a.b -> c");
+  let root = parse_ok(tokens.clone());
+  match root.node {
+    Node::Block(ref stmts) => assert_eq!(offsets(&tokens, stmts[0].span), (0, 15)),
+    _ => panic!("expected a block"),
+  }
+
+  let err = parse_error("import a.");
+  assert_eq!(err.message, "expected name, found end of line");
+
+  let err = parse_error("import -> a");
+  assert_eq!(err.message, "expected name, found `->`");
+
+  let err = parse_error("import a -> b.c");
+  assert_eq!(err.message, "expected one of `;`, end of line, found `.`");
+}
+
+#[test]
+fn test_save_stmt() {
+  test_parse(
+    "save x",
+    &parse_stmt,
+    Ok(sp(Node::Save {
+      name: String::from("x"),
+      rhs: None,
+      doc: None,
+    })),
+  );
+
+  test_parse(
+    "save f = fn(x)
+       return x",
+    &parse_stmt,
+    Ok(sp(Node::Save {
+      name: String::from("f"),
+      rhs: Some(bx(Node::Func {
+        params: vec![String::from("x")],
+        body: vec![sp(Node::Return(Some(bx(Node::Name(String::from("x"))))))],
+        doc: None,
+      })),
+      doc: None,
+    })),
+  );
+
+  let err = parse_error("save x.y");
+  assert_eq!(err.message, "expected one of `;`, end of line, found `.`");
 }
 
 #[test]
 fn test_return_stmt() {
-  test_parse("return", &parse_stmt, Ok(Node::Return(None)));
+  test_parse("return", &parse_stmt, Ok(sp(Node::Return(None))));
 
   test_parse(
     "return 5",
     &parse_stmt,
-    Ok(Node::Return(Some(Box::new(Node::Int(5))))),
+    Ok(sp(Node::Return(Some(bx(Node::Int(5)))))),
   );
 
   test_parse(
     "return fn()
        return 5",
     &parse_stmt,
-    Ok(Node::Return(Some(Box::new(Node::Func {
+    Ok(sp(Node::Return(Some(bx(Node::Func {
       params: Vec::new(),
-      body: vec![Node::Return(Some(Box::new(Node::Int(5))))],
-    })))),
+      body: vec![sp(Node::Return(Some(bx(Node::Int(5)))))],
+      doc: None,
+    }))))),
   );
 }
 
@@ -361,44 +1102,112 @@ fn test_if_stmt() {
     "if true
        pass",
     &parse_stmt,
-    Ok(Node::If {
-      cond: Box::new(Node::Bool(true)),
-      body: vec![Node::Pass],
+    Ok(sp(Node::If {
+      cond: bx(Node::Bool(true)),
+      body: vec![sp(Node::Pass)],
       els: None,
-    }),
+    })),
   );
 
   test_parse(
-    "else if true
-       pass",
+    "if a
+  x
+else
+  y",
     &parse_stmt,
-    Ok(Node::ElseIf {
-      cond: Box::new(Node::Bool(true)),
-      body: vec![Node::Pass],
-    }),
+    Ok(sp(Node::If {
+      cond: bx(Node::Name(String::from("a"))),
+      body: vec![sp(Node::Stmt(bx(Node::Name(String::from("x")))))],
+      els: Some(bx(Node::Else {
+        body: vec![sp(Node::Stmt(bx(Node::Name(String::from("y")))))],
+      })),
+    })),
   );
 
   test_parse(
-    "else
-       pass",
+    "if a
+  x
+else if b
+  y
+else if c
+  z",
     &parse_stmt,
-    Ok(Node::Else {
-      body: vec![Node::Pass],
-    }),
+    Ok(sp(Node::If {
+      cond: bx(Node::Name(String::from("a"))),
+      body: vec![sp(Node::Stmt(bx(Node::Name(String::from("x")))))],
+      els: Some(bx(Node::If {
+        cond: bx(Node::Name(String::from("b"))),
+        body: vec![sp(Node::Stmt(bx(Node::Name(String::from("y")))))],
+        els: Some(bx(Node::If {
+          cond: bx(Node::Name(String::from("c"))),
+          body: vec![sp(Node::Stmt(bx(Node::Name(String::from("z")))))],
+          els: None,
+        })),
+      })),
+    })),
+  );
+
+  // the inner else belongs to the inner if
+  test_parse(
+    "if a
+  if b
+    x
+  else
+    y
+else
+  z",
+    &parse_stmt,
+    Ok(sp(Node::If {
+      cond: bx(Node::Name(String::from("a"))),
+      body: vec![sp(Node::If {
+        cond: bx(Node::Name(String::from("b"))),
+        body: vec![sp(Node::Stmt(bx(Node::Name(String::from("x")))))],
+        els: Some(bx(Node::Else {
+          body: vec![sp(Node::Stmt(bx(Node::Name(String::from("y")))))],
+        })),
+      })],
+      els: Some(bx(Node::Else {
+        body: vec![sp(Node::Stmt(bx(Node::Name(String::from("z")))))],
+      })),
+    })),
   );
 }
 
+#[test]
+fn test_orphan_else() {
+  let tokens = get_tokens("x = 1\nelse\n  pass\ny = 2");
+  let (root, errors) = parse(tokens.clone()).unwrap();
+
+  assert_eq!(errors.len(), 1);
+  assert_eq!(errors[0].kind, MissingIf);
+  assert_eq!(offsets(&tokens, errors[0].span.unwrap()), (6, 10));
+  assert_eq!(errors[0].message, "`else` without a matching `if`");
+
+  match root.node {
+    Node::Block(ref stmts) => {
+      assert_eq!(stmts.len(), 3);
+      assert_eq!(stmts[1].node, Node::Error);
+    }
+    _ => panic!("expected a block"),
+  }
+
+  // an else can't come after an unrelated statement
+  let errors = parse(get_tokens("if a\n  x\ny = 2\nelse\n  z")).unwrap().1;
+  assert_eq!(errors.len(), 1);
+  assert_eq!(errors[0].kind, MissingIf);
+}
+
 #[test]
 fn test_for_stmt() {
   test_parse(
     "for x in true
        pass",
     &parse_stmt,
-    Ok(Node::For {
-      decl: Var::Single(String::from("x")),
-      expr: Box::new(Node::Bool(true)),
-      body: vec![Node::Pass],
-    }),
+    Ok(sp(Node::For {
+      decl: sp(Var::Single(String::from("x"))),
+      expr: bx(Node::Bool(true)),
+      body: vec![sp(Node::Pass)],
+    })),
   );
 }
 
@@ -408,10 +1217,10 @@ fn test_while_stmt() {
     "while true
        pass",
     &parse_stmt,
-    Ok(Node::While {
-      expr: Box::new(Node::Bool(true)),
-      body: vec![Node::Pass],
-    }),
+    Ok(sp(Node::While {
+      expr: bx(Node::Bool(true)),
+      body: vec![sp(Node::Pass)],
+    })),
   );
 }
 
@@ -421,9 +1230,9 @@ fn test_loop_stmt() {
     "loop
        pass",
     &parse_stmt,
-    Ok(Node::Loop {
-      body: vec![Node::Pass],
-    }),
+    Ok(sp(Node::Loop {
+      body: vec![sp(Node::Pass)],
+    })),
   );
 }
 
@@ -431,81 +1240,504 @@ fn test_loop_stmt() {
 fn test_place() {
   test_parse(
     "x",
-    &parse_place,
-    Ok(Place::Single(Box::new(Node::Name(String::from("x"))))),
+    &|it| parse_il_expr(it).map(into_place),
+    Ok(sp(Place::Single(bx(Node::Name(String::from("x")))))),
   );
 
   test_parse(
     "[x]",
-    &parse_place,
-    Ok(Place::Multi(vec![
-      Place::Single(Box::new(Node::Name(String::from("x")))),
-    ])),
+    &|it| parse_il_expr(it).map(into_place),
+    Ok(sp(Place::Multi(vec![sp(Place::Single(bx(Node::Name(
+      String::from("x"),
+    ))))]))),
   );
 
   test_parse(
     "[x.y]",
-    &parse_place,
-    Ok(Place::Multi(vec![
-      Place::Single(Box::new(Node::Index {
-        lhs: Box::new(Node::Name(String::from("x"))),
-        rhs: Box::new(Node::Str(String::from("y"))),
-      })),
-    ])),
+    &|it| parse_il_expr(it).map(into_place),
+    Ok(sp(Place::Multi(vec![sp(Place::Single(bx(Node::Index {
+      lhs: bx(Node::Name(String::from("x"))),
+      rhs: bx(Node::Str(String::from("y"))),
+    })))]))),
   );
 
   test_parse(
     "[x, y]",
-    &parse_place,
-    Ok(Place::Multi(vec![
-      Place::Single(Box::new(Node::Name(String::from("x")))),
-      Place::Single(Box::new(Node::Name(String::from("y")))),
-    ])),
+    &|it| parse_il_expr(it).map(into_place),
+    Ok(sp(Place::Multi(vec![
+      sp(Place::Single(bx(Node::Name(String::from("x"))))),
+      sp(Place::Single(bx(Node::Name(String::from("y"))))),
+    ]))),
   );
 
   test_parse(
     "[[x, y], z]",
-    &parse_place,
-    Ok(Place::Multi(vec![
-      Place::Multi(vec![
-        Place::Single(Box::new(Node::Name(String::from("x")))),
-        Place::Single(Box::new(Node::Name(String::from("y")))),
-      ]),
-      Place::Single(Box::new(Node::Name(String::from("z")))),
-    ])),
+    &|it| parse_il_expr(it).map(into_place),
+    Ok(sp(Place::Multi(vec![
+      sp(Place::Multi(vec![
+        sp(Place::Single(bx(Node::Name(String::from("x"))))),
+        sp(Place::Single(bx(Node::Name(String::from("y"))))),
+      ])),
+      sp(Place::Single(bx(Node::Name(String::from("z"))))),
+    ]))),
   );
 
   test_parse(
     "[x, [y, z]]",
-    &parse_place,
-    Ok(Place::Multi(vec![
-      Place::Single(Box::new(Node::Name(String::from("x")))),
-      Place::Multi(vec![
-        Place::Single(Box::new(Node::Name(String::from("y")))),
-        Place::Single(Box::new(Node::Name(String::from("z")))),
-      ]),
-    ])),
+    &|it| parse_il_expr(it).map(into_place),
+    Ok(sp(Place::Multi(vec![
+      sp(Place::Single(bx(Node::Name(String::from("x"))))),
+      sp(Place::Multi(vec![
+        sp(Place::Single(bx(Node::Name(String::from("y"))))),
+        sp(Place::Single(bx(Node::Name(String::from("z"))))),
+      ])),
+    ]))),
   );
 
   test_parse(
     "[[x], [y]]",
-    &parse_place,
-    Ok(Place::Multi(vec![
-      Place::Multi(vec![Place::Single(Box::new(Node::Name(String::from("x"))))]),
-      Place::Multi(vec![Place::Single(Box::new(Node::Name(String::from("y"))))]),
-    ])),
+    &|it| parse_il_expr(it).map(into_place),
+    Ok(sp(Place::Multi(vec![
+      sp(Place::Multi(vec![sp(Place::Single(bx(Node::Name(
+        String::from("x"),
+      ))))])),
+      sp(Place::Multi(vec![sp(Place::Single(bx(Node::Name(
+        String::from("y"),
+      ))))])),
+    ]))),
   );
 
   test_parse(
     "[x, [y, z], q]",
-    &parse_place,
-    Ok(Place::Multi(vec![
-      Place::Single(Box::new(Node::Name(String::from("x")))),
-      Place::Multi(vec![
-        Place::Single(Box::new(Node::Name(String::from("y")))),
-        Place::Single(Box::new(Node::Name(String::from("z")))),
-      ]),
-      Place::Single(Box::new(Node::Name(String::from("q")))),
-    ])),
+    &|it| parse_il_expr(it).map(into_place),
+    Ok(sp(Place::Multi(vec![
+      sp(Place::Single(bx(Node::Name(String::from("x"))))),
+      sp(Place::Multi(vec![
+        sp(Place::Single(bx(Node::Name(String::from("y"))))),
+        sp(Place::Single(bx(Node::Name(String::from("z"))))),
+      ])),
+      sp(Place::Single(bx(Node::Name(String::from("q"))))),
+    ]))),
+  );
+}
+
+#[test]
+fn test_errors() {
+  let tokens = get_tokens("for x y\n  pass");
+  let err = parse(tokens.clone()).unwrap().1.remove(0);
+  assert_eq!(
+    err.kind,
+    UnexpectedToken(lexer::Token::Name(String::from("y")))
+  );
+  assert_eq!(offsets(&tokens, err.span.unwrap()), (6, 7));
+  assert_eq!(err.expected, vec![Expected::Token(lexer::Token::In)]);
+  assert_eq!(
+    err.message,
+    "expected `in` after for-loop declaration, found name `y`"
+  );
+
+  let err = parse_error("x = (1 + 2]");
+  assert_eq!(err.expected, vec![Expected::Token(lexer::Token::Par)]);
+  assert_eq!(err.message, "expected `)`, found `]`");
+
+  let err = parse_error("for 5 in x\n  pass");
+  assert_eq!(err.message, "expected one of `[`, name, found integer `5`");
+
+  let err = parse_error("x = *");
+  assert_eq!(err.expected, vec![Expected::Rule("expression")]);
+  assert_eq!(err.message, "expected expression, found `*`");
+
+  // no tokens at all, not even an EOF
+  let err = parse(vec![]).unwrap_err();
+  assert_eq!(err.kind, UnexpectedEOF);
+  assert_eq!(err.span, None);
+}
+
+#[test]
+fn test_recovery() {
+  let source = "x = )
+if y z
+  pass
+  a = 1
+while true
+  b = (]
+  c = 2
+d = 3";
+  let tokens = get_tokens(source);
+  let (root, errors) = parse(tokens.clone()).unwrap();
+
+  let kinds: Vec<ParseErrorKind> = errors.iter().map(|e| e.kind.clone()).collect();
+  assert_eq!(
+    kinds,
+    vec![
+      UnexpectedToken(lexer::Token::Par),
+      UnexpectedToken(lexer::Token::Name(String::from("z"))),
+      UnexpectedToken(lexer::Token::Sqr),
+    ]
+  );
+
+  match root.node {
+    Node::Block(ref stmts) => {
+      assert_eq!(offsets(&tokens, stmts[0].span), (0, 5));
+      assert_eq!(offsets(&tokens, stmts[1].span), (6, 27));
+    }
+    _ => panic!("expected a block"),
+  }
+
+  assert_eq!(
+    root.unspan(),
+    sp(Node::Block(vec![
+      sp(Node::Error),
+      sp(Node::Error),
+      sp(Node::While {
+        expr: bx(Node::Bool(true)),
+        body: vec![
+          sp(Node::Error),
+          sp(Node::Assn {
+            lhs: sp(Place::Single(bx(Node::Name(String::from("c"))))),
+            rhs: bx(Node::Int(2)),
+            doc: None,
+          }),
+        ],
+      }),
+      sp(Node::Assn {
+        lhs: sp(Place::Single(bx(Node::Name(String::from("d"))))),
+        rhs: bx(Node::Int(3)),
+        doc: None,
+      }),
+    ]))
+  );
+
+  // the error covers what the failed parse took, not just what was skipped
+  let tokens = get_tokens(
+    "w = 3 +
+v = [1,
+  2 3]",
+  );
+  let (root, errors) = parse(tokens.clone()).unwrap();
+  assert_eq!(errors.len(), 2);
+  match root.node {
+    Node::Block(ref stmts) => {
+      assert_eq!(offsets(&tokens, stmts[0].span), (0, 7));
+      assert_eq!(offsets(&tokens, stmts[1].span), (8, 22));
+    }
+    _ => panic!("expected a block"),
+  }
+}
+
+#[test]
+fn test_spans() {
+  let tokens = get_tokens("x.y = foo(1, (2 + 3)) * -z");
+  let root = parse_ok(tokens.clone());
+  assert_eq!(offsets(&tokens, root.span), (0, 26));
+
+  let stmts = match root.node {
+    Node::Block(stmts) => stmts,
+    _ => panic!("expected a block"),
+  };
+  assert_eq!(offsets(&tokens, stmts[0].span), (0, 26));
+
+  let (lhs, rhs) = match stmts[0].node {
+    Node::Assn {
+      ref lhs, ref rhs, ..
+    } => (lhs, rhs),
+    _ => panic!("expected an assignment"),
+  };
+  assert_eq!(offsets(&tokens, lhs.span), (0, 3));
+  assert_eq!(offsets(&tokens, rhs.span), (6, 26));
+
+  let (call, neg) = match rhs.node {
+    Node::BinExpr {
+      ref lhs, ref rhs, ..
+    } => (lhs, rhs),
+    _ => panic!("expected a binary expression"),
+  };
+  assert_eq!(offsets(&tokens, call.span), (6, 21));
+  assert_eq!(offsets(&tokens, neg.span), (24, 26));
+
+  match call.node {
+    Node::Call { ref func, ref args } => {
+      assert_eq!(offsets(&tokens, func.span), (6, 9));
+      assert_eq!(offsets(&tokens, args[0].span), (10, 11));
+      assert_eq!(offsets(&tokens, args[1].span), (13, 20));
+    }
+    _ => panic!("expected a call"),
+  }
+}
+
+#[test]
+fn test_block_spans() {
+  let tokens = get_tokens("while x\n  pass\n  y = 1\n");
+  let root = parse_ok(tokens.clone());
+
+  match root.node {
+    Node::Block(ref stmts) => match stmts[0].node {
+      Node::While { ref expr, ref body } => {
+        assert_eq!(offsets(&tokens, stmts[0].span), (0, 22));
+        assert_eq!(offsets(&tokens, expr.span), (6, 7));
+        assert_eq!(offsets(&tokens, body[0].span), (10, 14));
+        assert_eq!(offsets(&tokens, body[1].span), (17, 22));
+      }
+      _ => panic!("expected a while loop"),
+    },
+    _ => panic!("expected a block"),
+  }
+}
+
+#[test]
+fn test_semicolons() {
+  let assn = |name: &str, val: i64| {
+    sp(Node::Assn {
+      lhs: sp(Place::Single(bx(Node::Name(String::from(name))))),
+      rhs: bx(Node::Int(val)),
+      doc: None,
+    })
+  };
+
+  let root = parse_ok(get_tokens("x = 1; y = 2;\nz = 3;"));
+  assert_eq!(
+    root.unspan(),
+    sp(Node::Block(vec![assn("x", 1), assn("y", 2), assn("z", 3)]))
+  );
+
+  let root = parse_ok(get_tokens("loop\n  x = 1; break\ny = 2"));
+  assert_eq!(
+    root.unspan(),
+    sp(Node::Block(vec![
+      sp(Node::Loop {
+        body: vec![assn("x", 1), sp(Node::Break)],
+      }),
+      assn("y", 2),
+    ]))
+  );
+
+  let root = parse_ok(get_tokens("while poll(); x = 1"));
+  assert_eq!(
+    root.unspan(),
+    sp(Node::Block(vec![
+      sp(Node::While {
+        expr: bx(Node::Call {
+          func: bx(Node::Name(String::from("poll"))),
+          args: vec![],
+        }),
+        body: vec![],
+      }),
+      assn("x", 1),
+    ]))
+  );
+
+  let root = parse_ok(get_tokens("f = fn(x);"));
+  assert_eq!(
+    root.unspan(),
+    sp(Node::Block(vec![sp(Node::Assn {
+      lhs: sp(Place::Single(bx(Node::Name(String::from("f"))))),
+      rhs: bx(Node::Func {
+        params: vec![String::from("x")],
+        body: vec![],
+        doc: None,
+      }),
+      doc: None,
+    })]))
+  );
+
+  let root = parse_ok(get_tokens("return; x = 1"));
+  assert_eq!(
+    root.unspan(),
+    sp(Node::Block(vec![sp(Node::Return(None)), assn("x", 1)]))
+  );
+
+  let err = parse_error("x = 1 2");
+  assert_eq!(
+    err.expected,
+    vec![
+      Expected::Token(lexer::Token::Semi),
+      Expected::Token(lexer::Token::End),
+    ]
+  );
+
+  // a broken statement only takes down the rest of its own `;`
+  let (root, errors) = parse(get_tokens("x = ); y = 2")).unwrap();
+  assert_eq!(errors.len(), 1);
+  assert_eq!(
+    root.unspan(),
+    sp(Node::Block(vec![sp(Node::Error), assn("y", 2)]))
+  );
+
+  let err = parse_error("x = 1;; y = 2");
+  assert_eq!(err.message, "expected expression, found `;`");
+
+  let err = parse_error("while x\n;");
+  assert_eq!(err.message, "expected indented block, found end of line");
+}
+
+#[test]
+fn test_interp() {
+  test_parse(
+    "'hello ${name}, you have ${count + 1} items'",
+    &parse_quark,
+    Ok(sp(Node::Interp(vec![
+      sp(Node::Str(String::from("hello "))),
+      sp(Node::Name(String::from("name"))),
+      sp(Node::Str(String::from(", you have "))),
+      sp(Node::BinExpr {
+        lhs: bx(Node::Name(String::from("count"))),
+        op: lexer::Token::Add,
+        rhs: bx(Node::Int(1)),
+      }),
+      sp(Node::Str(String::from(" items"))),
+    ]))),
+  );
+
+  test_parse(
+    "\"${f({a = 1})[\"}\"]}${'${x}'}\"",
+    &parse_quark,
+    Ok(sp(Node::Interp(vec![
+      sp(Node::Index {
+        lhs: bx(Node::Call {
+          func: bx(Node::Name(String::from("f"))),
+          args: vec![sp(Node::Dict(vec![(
+            sp(Node::Str(String::from("a"))),
+            sp(Node::Int(1)),
+          )]))],
+        }),
+        rhs: bx(Node::Str(String::from("}"))),
+      }),
+      sp(Node::Interp(vec![sp(Node::Name(String::from("x")))])),
+    ]))),
+  );
+
+  test_parse(
+    "'cost: \\${x}'",
+    &parse_quark,
+    Ok(sp(Node::Str(String::from("cost: ${x}")))),
+  );
+
+  let err = parse_error("x = 'a ${b c} d'");
+  assert_eq!(
+    err.message,
+    "expected `}` after interpolated expression, found name `c`"
+  );
+
+  let err = parse_error("x = '${}'");
+  assert_eq!(err.message, "expected expression, found `}`");
+}
+
+#[test]
+fn test_interp_spans() {
+  let tokens = get_tokens("x = 'ab ${f(y)} c ${z}'");
+  let root = parse_ok(tokens.clone());
+
+  let parts = match root.node {
+    Node::Block(ref stmts) => match stmts[0].node {
+      Node::Assn { ref rhs, .. } => match rhs.node {
+        Node::Interp(ref parts) => parts.clone(),
+        _ => panic!("expected an interpolated string"),
+      },
+      _ => panic!("expected an assignment"),
+    },
+    _ => panic!("expected a block"),
+  };
+
+  let spans: Vec<(u64, u64)> = parts.iter().map(|p| offsets(&tokens, p.span)).collect();
+  assert_eq!(spans, vec![(5, 8), (10, 14), (15, 18), (20, 21)]);
+
+  match parts[1].node {
+    Node::Call { ref args, .. } => assert_eq!(offsets(&tokens, args[0].span), (12, 13)),
+    _ => panic!("expected a call"),
+  }
+
+  let err = parse_error("x = 'a ${b c} d'");
+  assert_eq!(
+    offsets(&get_tokens("x = 'a ${b c} d'"), err.span.unwrap()),
+    (11, 12)
+  );
+}
+
+#[test]
+fn test_doc_comments() {
+  let source = "## Adds things
+##
+## Really.
+add = fn(a, b)
+  ## The sum
+  var sum = a + b
+  return sum
+
+## An anonymous function
+fn()
+  pass
+";
+  let root = parse_ok(get_tokens(source));
+  assert_eq!(
+    root.unspan(),
+    sp(Node::Block(vec![
+      sp(Node::Assn {
+        lhs: sp(Place::Single(bx(Node::Name(String::from("add"))))),
+        rhs: bx(Node::Func {
+          params: vec![String::from("a"), String::from("b")],
+          body: vec![
+            sp(Node::Decl {
+              decl: sp(Var::Single(String::from("sum"))),
+              rhs: bx(Node::BinExpr {
+                lhs: bx(Node::Name(String::from("a"))),
+                op: lexer::Token::Add,
+                rhs: bx(Node::Name(String::from("b"))),
+              }),
+              doc: Some(String::from("The sum")),
+            }),
+            sp(Node::Return(Some(bx(Node::Name(String::from("sum")))))),
+          ],
+          doc: None,
+        }),
+        doc: Some(String::from("Adds things\n\nReally.")),
+      }),
+      sp(Node::Stmt(bx(Node::Func {
+        params: vec![],
+        body: vec![sp(Node::Pass)],
+        doc: Some(String::from("An anonymous function")),
+      }))),
+    ]))
+  );
+
+  let root = parse_ok(get_tokens(
+    "## Saved\nsave x = 1\n## Just a comment\nsave y",
+  ));
+  assert_eq!(
+    root.unspan(),
+    sp(Node::Block(vec![
+      sp(Node::Save {
+        name: String::from("x"),
+        rhs: Some(bx(Node::Int(1))),
+        doc: Some(String::from("Saved")),
+      }),
+      sp(Node::Save {
+        name: String::from("y"),
+        rhs: None,
+        doc: None,
+      }),
+    ]))
+  );
+
+  // a doc comment with nothing to document is just a comment
+  let root = parse_ok(get_tokens("x = 1\n## Section ##\nif x\n  pass"));
+  match root.node {
+    Node::Block(ref stmts) => assert_eq!(stmts.len(), 2),
+    _ => panic!("expected a block"),
+  }
+
+  parse_ok(get_tokens("x = 1\n## trailing"));
+  parse_ok(get_tokens("if x\n  ## at the end of a block\ny = 1"));
+
+  let root = parse_ok(get_tokens("## not for y\nreturn\ny = 1"));
+  assert_eq!(
+    root.unspan(),
+    sp(Node::Block(vec![
+      sp(Node::Return(None)),
+      sp(Node::Assn {
+        lhs: sp(Place::Single(bx(Node::Name(String::from("y"))))),
+        rhs: bx(Node::Int(1)),
+        doc: None,
+      }),
+    ]))
   );
 }
@@ -0,0 +1,85 @@
+use super::*;
+use codemap::CodeMap;
+use lexer;
+use parser;
+
+fn check_spanned(source: &str) -> Check {
+  let mut map = CodeMap::new();
+  let file = map.add_file(String::from("_test"), String::from(source));
+  let tokens = lexer::lex(&file).unwrap();
+  let (mut root, errors) = parser::parse(tokens).unwrap();
+  assert_eq!(errors, vec![]);
+  SemChecker::new().check(&mut root)
+}
+
+fn check(source: &str) -> Result<(), CheckErrorKind> {
+  check_spanned(source).map_err(|err| err.node)
+}
+
+// The offsets of an error's span from the start of the source
+fn error_offsets(source: &str) -> (u64, u64) {
+  let mut map = CodeMap::new();
+  let base = map.add_file(String::new(), String::new()).span.low();
+  let err = check_spanned(source).unwrap_err();
+  (err.span.low() - base, err.span.high() - base)
+}
+
+#[test]
+fn check_loops() {
+  assert_eq!(check("loop\n  break"), Ok(()));
+  assert_eq!(check("while x\n  if y\n    continue"), Ok(()));
+  assert_eq!(check("break"), Err(CheckErrorKind::NotInLoop));
+  assert_eq!(
+    check("if x\n  pass\nelse\n  continue"),
+    Err(CheckErrorKind::NotInLoop)
+  );
+
+  // a function inside a loop doesn't share it
+  assert_eq!(
+    check("loop\n  fn()\n    break"),
+    Err(CheckErrorKind::NotInLoop)
+  );
+  assert_eq!(check("while x\n  fn()\n    pass\n  break"), Ok(()));
+}
+
+#[test]
+fn check_save() {
+  assert_eq!(check("x = 1\nsave x"), Ok(()));
+  assert_eq!(check("save x\nx = 1"), Ok(()));
+  assert_eq!(check("var [a, [b, c]] = f()\nsave c"), Ok(()));
+  assert_eq!(check("[a, b] = f()\nsave a"), Ok(()));
+  assert_eq!(check("import a.b\nsave b"), Ok(()));
+  assert_eq!(check("import a.b -> c\nsave c"), Ok(()));
+  assert_eq!(check("save x = 1"), Ok(()));
+
+  assert_eq!(
+    check("save x"),
+    Err(CheckErrorKind::UndefinedSave(String::from("x")))
+  );
+  assert_eq!(
+    check("x.y = 1\nsave y"),
+    Err(CheckErrorKind::UndefinedSave(String::from("y")))
+  );
+  assert_eq!(
+    check("import a.b -> c\nsave b"),
+    Err(CheckErrorKind::UndefinedSave(String::from("b")))
+  );
+  assert_eq!(
+    check("if true\n  x = 1\nsave x"),
+    Err(CheckErrorKind::UndefinedSave(String::from("x")))
+  );
+  assert_eq!(
+    check("x = 1\nif true\n  save x"),
+    Err(CheckErrorKind::SaveNotTopLevel)
+  );
+
+  // the body of a saved function is checked too
+  assert_eq!(
+    check("save f = fn()\n  break"),
+    Err(CheckErrorKind::NotInLoop)
+  );
+
+  // errors point at the offending save
+  assert_eq!(error_offsets("x = 1\nsave y"), (6, 12));
+  assert_eq!(error_offsets("if x\n  save x = 1"), (7, 17));
+}